@@ -0,0 +1,85 @@
+extern crate dovahkiin;
+
+use dovahkiin::printer::format::format_source_with_width;
+use dovahkiin::printer::lisp::DEFAULT_WIDTH;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &str = "Usage: dovfmt [--check] [--width <columns>] [files...]
+
+Formats lisp query files in place. Reads stdin and writes stdout when no file is given.
+  --check    only report files that are not formatted, exit with 1 if there are any
+  --width    line width, default 80";
+
+fn main() {
+    let mut check = false;
+    let mut width = DEFAULT_WIDTH;
+    let mut files = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--check" => check = true,
+            "--width" => match args.next().and_then(|w| w.parse().ok()) {
+                Some(w) => width = w,
+                None => exit_with_usage(),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => exit_with_usage(),
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        let mut code = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut code) {
+            fail(&format!("cannot read stdin: {}", e));
+        }
+        let formatted = format_or_fail("<stdin>", &code, width);
+        if check {
+            if formatted != code {
+                process::exit(1);
+            }
+        } else if let Err(e) = io::stdout().write_all(formatted.as_bytes()) {
+            fail(&format!("cannot write stdout: {}", e));
+        }
+        return;
+    }
+    let mut unformatted = false;
+    for file in files {
+        let code = fs::read_to_string(&file)
+            .unwrap_or_else(|e| fail(&format!("cannot read {}: {}", file, e)));
+        let formatted = format_or_fail(&file, &code, width);
+        if formatted == code {
+            continue;
+        }
+        if check {
+            if let Err(e) = writeln!(io::stdout(), "{}", file) {
+                fail(&format!("cannot write stdout: {}", e));
+            }
+            unformatted = true;
+        } else if let Err(e) = fs::write(&file, formatted) {
+            fail(&format!("cannot write {}: {}", file, e));
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn format_or_fail(name: &str, code: &str, width: usize) -> String {
+    format_source_with_width(code, width).unwrap_or_else(|e| fail(&format!("{}: {}", name, e)))
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("dovfmt: {}", message);
+    process::exit(2)
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
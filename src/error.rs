@@ -0,0 +1,152 @@
+use span::Span;
+use std::error::Error;
+use std::fmt;
+use types::OwnedValue as Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    // input ended in the middle of a form, reading more input may complete it
+    Incomplete,
+    Io,
+    // malformed or incompatible binary encoding
+    Decode,
+    UnknownSymbol,
+    Arity,
+    Type,
+    Runtime,
+    User,
+    // evaluation ran out of its step, call depth, time or memory budget
+    LimitExceeded,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DovahkiinError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub payload: Option<Value>,
+    pub span: Option<Span>,
+    pub cause: Option<Box<DovahkiinError>>,
+}
+
+impl DovahkiinError {
+    pub fn new(kind: ErrorKind, message: String) -> DovahkiinError {
+        DovahkiinError {
+            kind,
+            message,
+            payload: None,
+            span: None,
+            cause: None,
+        }
+    }
+    pub fn lex(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Lex, message)
+    }
+    pub fn parse(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Parse, message)
+    }
+    pub fn incomplete(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Incomplete, message)
+    }
+    pub fn decode(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Decode, message)
+    }
+    pub fn unknown_symbol(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::UnknownSymbol, message)
+    }
+    pub fn arity(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Arity, message)
+    }
+    pub fn type_error(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Type, message)
+    }
+    pub fn runtime(message: String) -> DovahkiinError {
+        Self::new(ErrorKind::Runtime, message)
+    }
+    pub fn user(message: String, payload: Value) -> DovahkiinError {
+        Self::new(ErrorKind::User, message).with_payload(payload)
+    }
+    /// The payload is a map of the counters when the limit was hit
+    pub fn limit_exceeded(message: String, counters: Value) -> DovahkiinError {
+        Self::new(ErrorKind::LimitExceeded, message).with_payload(counters)
+    }
+    /// Wraps an error with more context. The kind is kept so callers can still tell
+    /// what went wrong at the bottom of the chain.
+    pub fn wrap(cause: DovahkiinError, message: String) -> DovahkiinError {
+        Self::new(cause.kind, message).caused_by(cause)
+    }
+
+    pub fn with_span(mut self, span: Span) -> DovahkiinError {
+        self.span = Some(span);
+        self
+    }
+    /// Only set the span when there is none, so the innermost form is reported
+    pub fn or_span(mut self, span: Span) -> DovahkiinError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+    pub fn with_payload(mut self, payload: Value) -> DovahkiinError {
+        self.payload = Some(payload);
+        self
+    }
+    pub fn caused_by(mut self, cause: DovahkiinError) -> DovahkiinError {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Input so far is fine but ends in the middle of a form. A REPL can keep buffering.
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == ErrorKind::Incomplete
+    }
+
+    pub fn root_cause(&self) -> &DovahkiinError {
+        match self.cause {
+            Some(ref cause) => cause.root_cause(),
+            None => self,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            &ErrorKind::Lex => "lex",
+            &ErrorKind::Parse => "parse",
+            &ErrorKind::Incomplete => "incomplete",
+            &ErrorKind::Io => "io",
+            &ErrorKind::Decode => "decode",
+            &ErrorKind::UnknownSymbol => "unknown symbol",
+            &ErrorKind::Arity => "arity",
+            &ErrorKind::Type => "type",
+            &ErrorKind::Runtime => "runtime",
+            &ErrorKind::User => "user",
+            &ErrorKind::LimitExceeded => "limit exceeded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for DovahkiinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind, self.message)?;
+        if let Some(ref span) = self.span {
+            write!(f, ", at {}", span)?;
+        }
+        if let Some(ref cause) = self.cause {
+            write!(f, "\n  caused by {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DovahkiinError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.cause {
+            Some(ref cause) => Some(cause.as_ref()),
+            None => None,
+        }
+    }
+}
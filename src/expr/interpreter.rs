@@ -1,59 +1,426 @@
+use error::DovahkiinError;
 use expr::symbols::misc;
 use expr::SExpr;
-use std::cell::RefCell;
-use std::cell::RefMut;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::{Cell, RefCell, RefMut};
 use std::collections::{HashMap, LinkedList};
-use std::rc::Rc;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use types::OwnedMap;
+use types::OwnedValue as Value;
 
-thread_local!(pub static ENV: RefCell<Rc<Envorinment>> = RefCell::new(Rc::new(Envorinment::new())));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scoping {
+    // names are looked up where the code is written, lambdas capture the frames they are created in
+    Lexical,
+    // names are looked up in the bindings of the running callers, for scripts written before closures
+    Dynamic,
+}
 
+/// Bindings of a let form or a function call, on top of the frame it was evaluated in.
+/// Frames are never changed once created, so closures can share them.
+#[derive(Debug)]
+pub struct Frame {
+    bindings: Vec<(u64, Arc<SExpr>)>,
+    parent: Option<Arc<Frame>>,
+}
+
+impl Frame {
+    pub fn new(bindings: Vec<(u64, Arc<SExpr>)>, parent: Option<Arc<Frame>>) -> Frame {
+        Frame { bindings, parent }
+    }
+    pub fn get(&self, id: u64) -> Option<Arc<SExpr>> {
+        let mut frame = self;
+        loop {
+            // later bindings shadow earlier ones, like (let [x 1 x 2] x)
+            if let Some(&(_, ref value)) = frame.bindings.iter().rev().find(|b| b.0 == id) {
+                return Some(value.clone());
+            }
+            match frame.parent {
+                Some(ref parent) => frame = parent,
+                None => return None,
+            }
+        }
+    }
+    /// Bindings seen from this frame ordered by id
+    pub fn visible_bindings(&self) -> Vec<(u64, SExpr)> {
+        let mut bindings: Vec<(u64, SExpr)> = Vec::new();
+        let mut frame = Some(self);
+        while let Some(current) = frame {
+            for &(id, ref value) in current.bindings.iter().rev() {
+                if bindings.iter().all(|b| b.0 != id) {
+                    bindings.push((id, (**value).clone()));
+                }
+            }
+            frame = current.parent.as_ref().map(|parent| &**parent);
+        }
+        bindings.sort_by_key(|b| b.0);
+        bindings
+    }
+}
+
+/// Frame a lambda was created in. Lambdas from the top level or with dynamic scoping capture nothing.
+/// Captured frames are compared and encoded by the bindings they make visible.
+#[derive(Debug, Clone, Default)]
+pub struct Captured(pub Option<Arc<Frame>>);
+
+impl Captured {
+    pub fn bindings(&self) -> Vec<(u64, SExpr)> {
+        match self.0 {
+            Some(ref frame) => frame.visible_bindings(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl PartialEq for Captured {
+    fn eq(&self, other: &Captured) -> bool {
+        self.bindings() == other.bindings()
+    }
+}
+
+impl Eq for Captured {}
+
+impl Serialize for Captured {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.bindings().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Captured {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Captured, D::Error> {
+        let bindings: Vec<(u64, SExpr)> = Vec::deserialize(deserializer)?;
+        Ok(Captured(if bindings.is_empty() {
+            None
+        } else {
+            let bindings = bindings
+                .into_iter()
+                .map(|(id, value)| (id, Arc::new(value)))
+                .collect();
+            Some(Arc::new(Frame::new(bindings, None)))
+        }))
+    }
+}
+
+/// Budget of one `Interpreter::eval`, no limit is set by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    // expressions evaluated
+    pub max_steps: Option<u64>,
+    // nested function calls, calls in tail position do not nest
+    pub max_depth: Option<usize>,
+    pub timeout: Option<Duration>,
+    // approximate bytes of the vectors, arrays, maps and strings built, see `vec_size`
+    pub max_memory: Option<usize>,
+}
+
+// Counters of the running evaluation, checked against the limits
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    steps: u64,
+    depth: usize,
+    memory: usize,
+    started: Instant,
+}
+
+impl Budget {
+    fn start() -> Budget {
+        Budget {
+            steps: 0,
+            depth: 0,
+            memory: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// State of one interpreter, passed to everything evaluating in it.
+/// Each interpreter owns one, so interpreters can nest and move between threads.
 #[derive(Debug)]
 pub struct Envorinment {
-    pub bindings: RefCell<HashMap<u64, LinkedList<Rc<SExpr>>>>,
+    // global bindings from def and defunc, and all bindings with dynamic scoping
+    pub bindings: RefCell<HashMap<u64, LinkedList<Arc<SExpr>>>>,
+    // frame of the running let form or function call with lexical scoping
+    pub frame: RefCell<Option<Arc<Frame>>>,
+    pub scoping: Cell<Scoping>,
+    pub limits: Cell<Limits>,
+    budget: Cell<Budget>,
+    // an evaluation is running, nested ones share its budget
+    evaluating: Cell<bool>,
 }
 
 impl Envorinment {
     pub fn new() -> Envorinment {
         Envorinment {
             bindings: RefCell::new(HashMap::new()),
+            frame: RefCell::new(None),
+            scoping: Cell::new(Scoping::Lexical),
+            limits: Cell::new(Limits::default()),
+            budget: Cell::new(Budget::start()),
+            evaluating: Cell::new(false),
         }
     }
-    pub fn get_mut_bindings(&self) -> RefMut<HashMap<u64, LinkedList<Rc<SExpr>>>> {
+    pub fn get_mut_bindings(&self) -> RefMut<HashMap<u64, LinkedList<Arc<SExpr>>>> {
         self.bindings.borrow_mut()
     }
+
+    pub fn bind_shared(&self, id: u64, val_rc: Arc<SExpr>) {
+        self.get_mut_bindings()
+            .entry(id)
+            .or_insert_with(|| LinkedList::new())
+            .push_front(val_rc);
+    }
+    pub fn bind(&self, id: u64, val: SExpr) {
+        self.bind_shared(id, Arc::new(val))
+    }
+    pub fn unbind(&self, id: u64) {
+        let mut bindings = self.get_mut_bindings();
+        let unbound = match bindings.get_mut(&id) {
+            Some(stack) => {
+                stack.pop_front();
+                stack.is_empty()
+            }
+            None => false,
+        };
+        // no empty stacks are left, so the bindings are the same as before binding
+        if unbound {
+            bindings.remove(&id);
+        }
+    }
+
+    /// Value bound to the symbol, from the running frames first and then the global bindings
+    pub fn lookup(&self, id: u64) -> Option<Arc<SExpr>> {
+        let frame = self.frame.borrow();
+        if let Some(value) = frame.as_ref().and_then(|frame| frame.get(id)) {
+            return Some(value);
+        }
+        let bindings = self.bindings.borrow();
+        bindings.get(&id).and_then(|stack| stack.front().cloned())
+    }
+    pub fn scoping(&self) -> Scoping {
+        self.scoping.get()
+    }
+    pub fn current_frame(&self) -> Option<Arc<Frame>> {
+        self.frame.borrow().clone()
+    }
+    /// Evaluates with the frame as the running one, the previous frame is back after, also on errors
+    pub fn with_frame<T, F>(&self, frame: Option<Arc<Frame>>, eval: F) -> Result<T, DovahkiinError>
+    where
+        F: FnOnce() -> Result<T, DovahkiinError>,
+    {
+        let mut previous = Some(self.frame.replace(frame));
+        let _restore = OnDrop(|| {
+            self.frame.replace(previous.take().unwrap());
+        });
+        eval()
+    }
+
+    /// Counts one evaluation step, failing when the steps or the time of the evaluation run out
+    pub fn step(&self) -> Result<(), DovahkiinError> {
+        let limits = self.limits.get();
+        let mut budget = self.budget.get();
+        budget.steps += 1;
+        self.budget.set(budget);
+        if limits.max_steps.map_or(false, |max| budget.steps > max) {
+            return Err(limit_exceeded("step limit", &budget));
+        }
+        if limits
+            .timeout
+            .map_or(false, |timeout| budget.started.elapsed() > timeout)
+        {
+            return Err(limit_exceeded("timeout", &budget));
+        }
+        Ok(())
+    }
+    /// Evaluates a function call one level deeper, failing when the call depth runs out
+    pub fn with_call<T, F>(&self, eval: F) -> Result<T, DovahkiinError>
+    where
+        F: FnOnce() -> Result<T, DovahkiinError>,
+    {
+        let mut budget = self.budget.get();
+        budget.depth += 1;
+        self.budget.set(budget);
+        let _leave = OnDrop(|| {
+            let mut budget = self.budget.get();
+            budget.depth -= 1;
+            self.budget.set(budget);
+        });
+        match self.limits.get().max_depth {
+            Some(max) if budget.depth > max => Err(limit_exceeded("call depth limit", &budget)),
+            _ => eval(),
+        }
+    }
+    /// Counts bytes about to be allocated, failing when the memory of the evaluation runs out
+    pub fn allocate(&self, bytes: usize) -> Result<(), DovahkiinError> {
+        let mut budget = self.budget.get();
+        budget.memory = budget.memory.saturating_add(bytes);
+        self.budget.set(budget);
+        match self.limits.get().max_memory {
+            Some(max) if budget.memory > max => Err(limit_exceeded("memory quota", &budget)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Runs the cleanup when dropped, also when unwinding from a panic
+struct OnDrop<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+/// Dynamic bindings of a let form or a function call. They are unbound in reverse order
+/// when the scope is dropped, so a body failing half way does not leave them behind.
+pub struct BindingScope<'a> {
+    env: &'a Envorinment,
+    ids: Vec<u64>,
+}
+
+impl<'a> BindingScope<'a> {
+    pub fn new(env: &'a Envorinment) -> BindingScope<'a> {
+        BindingScope {
+            env,
+            ids: Vec::new(),
+        }
+    }
+    pub fn bind(&mut self, id: u64, val: SExpr) {
+        self.env.bind(id, val);
+        self.ids.push(id);
+    }
+}
+
+impl<'a> Drop for BindingScope<'a> {
+    fn drop(&mut self) {
+        for &id in self.ids.iter().rev() {
+            self.env.unbind(id);
+        }
+    }
+}
+
+fn limit_exceeded(limit: &str, budget: &Budget) -> DovahkiinError {
+    let elapsed = budget.started.elapsed();
+    let mut counters = OwnedMap::new();
+    counters.insert("limit", Value::String(limit.to_string()));
+    counters.insert("steps", Value::U64(budget.steps));
+    counters.insert("depth", Value::U64(budget.depth as u64));
+    counters.insert("memory", Value::U64(budget.memory as u64));
+    counters.insert("elapsed_ms", Value::U64(elapsed.as_millis() as u64));
+    DovahkiinError::limit_exceeded(
+        format!(
+            "Evaluation exceeded its {}, after {} steps at call depth {} in {}ms \
+             with {} bytes allocated",
+            limit,
+            budget.steps,
+            budget.depth,
+            elapsed.as_millis(),
+            budget.memory
+        ),
+        Value::Map(counters),
+    )
+}
+
+/// Approximate bytes taken by the value. Scalars count their encoded size,
+/// arrays and maps also count a slot for each item.
+pub fn value_size(value: &Value) -> usize {
+    match value {
+        &Value::Array(ref array) => array
+            .iter()
+            .map(|item| mem::size_of::<Value>() + value_size(item))
+            .sum(),
+        &Value::PrimArray(ref array) => array.size(),
+        &Value::Map(ref map) => {
+            let fields: usize = map.fields.iter().map(|field| field.len()).sum();
+            let values: usize = map
+                .map
+                .values()
+                .map(|value| mem::size_of::<(u64, Value)>() + value_size(value))
+                .sum();
+            fields + values
+        }
+        &Value::Null | &Value::NA => 0,
+        _ => value.base_size(),
+    }
+}
+
+/// Bytes of the slots of a vector. Items are counted where they are built, not again
+/// by the collections they are moved into.
+pub fn vec_size(len: usize) -> usize {
+    len * mem::size_of::<SExpr>()
 }
 
-pub fn eval_all(exprs: Vec<SExpr>) -> Result<Vec<SExpr>, String> {
+/// Bytes of the slots of an array, see `vec_size`
+pub fn array_size(len: usize) -> usize {
+    len * mem::size_of::<Value>()
+}
+
+/// Bytes of the names and slots of a map with these fields, see `vec_size`
+pub fn map_size<'a, I>(fields: I) -> usize
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    fields
+        .into_iter()
+        .map(|field| field.len() + mem::size_of::<(u64, Value)>())
+        .sum()
+}
+
+pub fn eval_all(exprs: Vec<SExpr>, env: &Envorinment) -> Result<Vec<SExpr>, DovahkiinError> {
     let mut result = Vec::with_capacity(exprs.len());
     for expr in exprs {
-        result.push(expr.eval()?);
+        result.push(expr.eval(env)?);
     }
     Ok(result)
 }
 
-pub fn do_eval(exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    misc::do_(exprs)
+pub fn do_eval(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    misc::do_(exprs, env)
 }
 
+/// Interpreters are independent of each other and `Send`, a server can keep a pool of them
+/// for worker threads. One interpreter evaluates on one thread at a time.
 #[derive(Debug)]
 pub struct Interpreter {
-    env: Rc<Envorinment>,
+    env: Envorinment,
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
         Interpreter {
-            env: Rc::new(Envorinment::new()),
+            env: Envorinment::new(),
         }
     }
-    pub fn set_env(&self) {
-        ENV.with(|env| {
-            let mut env_borrowed = env.borrow_mut();
-            *env_borrowed = self.env.clone();
-        });
+    pub fn env(&self) -> &Envorinment {
+        &self.env
+    }
+    /// Lexical by default, dynamic scoping is kept for existing scripts
+    pub fn set_scoping(&self, scoping: Scoping) {
+        self.env.scoping.set(scoping);
     }
-    pub fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, String> {
-        self.set_env();
-        do_eval(exprs)
+    pub fn scoping(&self) -> Scoping {
+        self.env.scoping.get()
+    }
+    pub fn set_limits(&self, limits: Limits) {
+        self.env.limits.set(limits);
+    }
+    pub fn limits(&self) -> Limits {
+        self.env.limits.get()
+    }
+    /// Each evaluation starts with the whole budget of the limits, and at the top level.
+    /// Evaluations nested in a running one, like from a native symbol, share its budget.
+    pub fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
+        let env = &self.env;
+        let outermost = !env.evaluating.replace(true);
+        if outermost {
+            env.budget.set(Budget::start());
+        }
+        let _finish = OnDrop(|| {
+            if outermost {
+                env.evaluating.set(false);
+            }
+        });
+        env.with_frame(None, || do_eval(exprs, env))
     }
 }
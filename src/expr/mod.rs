@@ -1,53 +1,107 @@
-use expr::interpreter::ENV;
-use std::borrow::Borrow;
-use std::rc::Rc;
+use error::DovahkiinError;
+use expr::interpreter::{Captured, Envorinment};
+use printer::lisp::print_expr;
+use span::Span;
+use std::fmt;
 use types::OwnedValue as Value;
 
 #[macro_use]
 pub mod symbols;
 pub mod interpreter;
+pub mod wire;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SExpr {
     Symbol(String),
     ISymbol(u64, String),
+    // Keyword as :name, the id is the key_hash of the name to look up maps without rehashing
+    Keyword(u64, String),
     Value(Value),
+    // Number literal without unit, typed by the other operands or the default type
+    Untyped(Value),
     List(Vec<SExpr>),
     Vec(Vec<SExpr>),
-    LAMBDA(Vec<SExpr>, Vec<SExpr>),
+    // parameters, body and the frame the lambda was created in
+    LAMBDA(Vec<SExpr>, Vec<SExpr>, Captured),
+    // Form read from source, the span points to where it was written
+    Located(Span, Box<SExpr>),
 }
 
 impl SExpr {
-    pub fn eval(self) -> Result<SExpr, String> {
+    pub fn eval(self, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+        env.step()?;
         match self {
             SExpr::List(exprs) => {
                 if exprs.len() == 0 {
                     Ok(SExpr::Value(Value::Null))
                 } else {
                     let mut iter = exprs.into_iter();
-                    let func = iter.next().unwrap().eval()?;
-                    Ok(symbols::functions::eval_function(&func, iter.collect())?)
+                    let func = match iter.next().unwrap() {
+                        // symbols are resolved by eval_function to keep their names for errors
+                        symbol @ SExpr::ISymbol(_, _) => symbol,
+                        head => head.eval(env)?,
+                    };
+                    Ok(symbols::functions::eval_function(
+                        &func,
+                        iter.collect(),
+                        env,
+                    )?)
                 }
             }
             SExpr::ISymbol(symbol_id, _) => {
-                let mut env_bind_ref: Option<Rc<SExpr>> = None;
-                ENV.with(|env| {
-                    let env_borrowed = env.borrow();
-                    let bindings = env_borrowed.get_mut_bindings();
-                    env_bind_ref = if let Some(binding_list) = bindings.get(&symbol_id) {
-                        binding_list.front().cloned()
-                    } else {
-                        None
+                if let Some(binding) = env.lookup(symbol_id) {
+                    match *binding {
+                        // bindings hold evaluated values, untyped ones bound by the host get a type
+                        SExpr::Untyped(ref value) => Ok(SExpr::Value(value.clone())),
+                        ref bind_expr => Ok(bind_expr.clone()),
                     }
-                });
-                if let Some(binding) = env_bind_ref {
-                    let bind_expr: &SExpr = binding.borrow();
-                    Ok(bind_expr.clone())
                 } else {
                     Ok(self)
                 }
             }
+            SExpr::Located(span, expr) => expr.eval(env).map_err(|e| e.or_span(span)),
+            SExpr::Untyped(value) => Ok(SExpr::Value(value)),
             _ => Ok(self),
         }
     }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            &SExpr::Located(span, _) => Some(span),
+            _ => None,
+        }
+    }
+
+    /// Drops source locations from the whole tree, used when code is turned into data
+    pub fn strip_locations(self) -> SExpr {
+        match self {
+            SExpr::Located(_, expr) => expr.strip_locations(),
+            SExpr::List(exprs) => {
+                SExpr::List(exprs.into_iter().map(|e| e.strip_locations()).collect())
+            }
+            SExpr::Vec(exprs) => {
+                SExpr::Vec(exprs.into_iter().map(|e| e.strip_locations()).collect())
+            }
+            SExpr::LAMBDA(params, body, captured) => SExpr::LAMBDA(
+                params,
+                body.into_iter().map(|e| e.strip_locations()).collect(),
+                captured,
+            ),
+            _ => self,
+        }
+    }
+
+    /// Expression without its source location, for code that inspects forms as data
+    pub fn unlocated(&self) -> &SExpr {
+        match self {
+            &SExpr::Located(_, ref expr) => expr.unlocated(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for SExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", print_expr(self))
+    }
 }
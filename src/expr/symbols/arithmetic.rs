@@ -1,60 +1,95 @@
 use super::*;
 
+// The operation gives None when the integer result overflows, which would panic or wrap
 macro_rules! reduce {
-    ($type: path, $values: ident, $exp: expr) => {{
+    ($type: path, $values: ident, $op: expr, |$result: ident, $n: ident| $exp: expr) => {{
         if let Some((first, elements)) = $values.split_first() {
             if let &SExpr::Value($type(n)) = first {
-                let mut result = n;
+                let mut $result = n;
                 for val in elements {
-                    if let &SExpr::Value($type(n)) = val {
-                        result = $exp(result, n);
+                    if let &SExpr::Value($type($n)) = val {
+                        $result = match $exp {
+                            Some(result) => result,
+                            None => return Err(overflow($op, &$values)),
+                        };
                     } else {
-                        return Err(format!(
+                        return Err(DovahkiinError::type_error(format!(
                             "Type not match, expect {} found {:?}",
                             stringify!($type),
                             val
-                        ));
+                        )));
                     }
                 }
-                Ok(SExpr::Value($type(result)))
+                Ok(SExpr::Value($type($result)))
             } else {
-                Err(format!(
+                Err(DovahkiinError::type_error(format!(
                     "Type not match on the first value, expect {} found {:?}",
                     stringify!($type),
                     first
-                ))
+                )))
             }
         } else {
-            Err("Cannot do reduce on values".to_string())
+            Err(DovahkiinError::arity(
+                "Cannot do reduce on values".to_string(),
+            ))
         }
     }};
 }
 
 macro_rules! add_ {
+    (float $type: ident, $values: ident) => {{
+        reduce!(Value::$type, $values, "Addition", |result, n| Some(
+            result + n
+        ))
+    }};
     ($type: ident, $values: ident) => {{
-        reduce!(Value::$type, $values, |result, n| { result + n })
+        reduce!(Value::$type, $values, "Addition", |result, n| result
+            .checked_add(n))
     }};
 }
 
 macro_rules! subtract_ {
+    (float $type: ident, $values: ident) => {{
+        reduce!(Value::$type, $values, "Subtraction", |result, n| Some(
+            result - n
+        ))
+    }};
     ($type: ident, $values: ident) => {{
-        reduce!(Value::$type, $values, |result, n| { result - n })
+        reduce!(Value::$type, $values, "Subtraction", |result, n| result
+            .checked_sub(n))
     }};
 }
 
 macro_rules! multiply_ {
+    (float $type: ident, $values: ident) => {{
+        reduce!(Value::$type, $values, "Multiplication", |result, n| Some(
+            result * n
+        ))
+    }};
     ($type: ident, $values: ident) => {{
-        reduce!(Value::$type, $values, |result, n| { result * n })
+        reduce!(Value::$type, $values, "Multiplication", |result, n| result
+            .checked_mul(n))
     }};
 }
 
+// the divisors are checked first, so None is only the overflow of MIN / -1
 macro_rules! divide_ {
+    (float $type: ident, $values: ident) => {{
+        reduce!(Value::$type, $values, "Division", |result, n| Some(
+            result / n
+        ))
+    }};
     ($type: ident, $values: ident) => {{
-        reduce!(Value::$type, $values, |result, n| { result / n })
+        reduce!(Value::$type, $values, "Division", |result, n| result
+            .checked_div(n))
     }};
 }
 
-pub fn add(values: Vec<SExpr>) -> Result<SExpr, String> {
+fn overflow(op: &str, values: &[SExpr]) -> DovahkiinError {
+    DovahkiinError::runtime(format!("{} overflow: {:?}", op, values))
+}
+
+pub fn add(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => add_!(U8, values),
         &SExpr::Value(Value::U16(_)) => add_!(U16, values),
@@ -64,13 +99,16 @@ pub fn add(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I16(_)) => add_!(I16, values),
         &SExpr::Value(Value::I32(_)) => add_!(I32, values),
         &SExpr::Value(Value::I64(_)) => add_!(I64, values),
-        &SExpr::Value(Value::F32(_)) => add_!(F32, values),
-        &SExpr::Value(Value::F64(_)) => add_!(F64, values),
-        _ => Err(format!("Type cannot be added {:?}", values)),
+        &SExpr::Value(Value::F32(_)) => add_!(float F32, values),
+        &SExpr::Value(Value::F64(_)) => add_!(float F64, values),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be added {:?}",
+            values
+        ))),
     }
 }
 
-pub fn subtract(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn subtract(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => subtract_!(U8, values),
         &SExpr::Value(Value::U16(_)) => subtract_!(U16, values),
@@ -80,13 +118,16 @@ pub fn subtract(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I16(_)) => subtract_!(I16, values),
         &SExpr::Value(Value::I32(_)) => subtract_!(I32, values),
         &SExpr::Value(Value::I64(_)) => subtract_!(I64, values),
-        &SExpr::Value(Value::F32(_)) => subtract_!(F32, values),
-        &SExpr::Value(Value::F64(_)) => subtract_!(F64, values),
-        _ => Err(format!("Type cannot be subtracted: {:?}", values)),
+        &SExpr::Value(Value::F32(_)) => subtract_!(float F32, values),
+        &SExpr::Value(Value::F64(_)) => subtract_!(float F64, values),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be subtracted: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn multiply(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn multiply(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => multiply_!(U8, values),
         &SExpr::Value(Value::U16(_)) => multiply_!(U16, values),
@@ -96,13 +137,37 @@ pub fn multiply(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I16(_)) => multiply_!(I16, values),
         &SExpr::Value(Value::I32(_)) => multiply_!(I32, values),
         &SExpr::Value(Value::I64(_)) => multiply_!(I64, values),
-        &SExpr::Value(Value::F32(_)) => multiply_!(F32, values),
-        &SExpr::Value(Value::F64(_)) => multiply_!(F64, values),
-        _ => Err(format!("Type cannot be multiplied: {:?}", values)),
+        &SExpr::Value(Value::F32(_)) => multiply_!(float F32, values),
+        &SExpr::Value(Value::F64(_)) => multiply_!(float F64, values),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be multiplied: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn divide(values: Vec<SExpr>) -> Result<SExpr, String> {
+// Integer division by zero would panic the host, it is an error instead
+fn check_divisors(values: &[SExpr]) -> Result<(), DovahkiinError> {
+    for value in values.iter().skip(1) {
+        match value {
+            &SExpr::Value(Value::U8(0))
+            | &SExpr::Value(Value::U16(0))
+            | &SExpr::Value(Value::U32(0))
+            | &SExpr::Value(Value::U64(0))
+            | &SExpr::Value(Value::I8(0))
+            | &SExpr::Value(Value::I16(0))
+            | &SExpr::Value(Value::I32(0))
+            | &SExpr::Value(Value::I64(0)) => {
+                return Err(DovahkiinError::runtime("Division by zero".to_string()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn divide(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
+    check_divisors(&values)?;
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => divide_!(U8, values),
         &SExpr::Value(Value::U16(_)) => divide_!(U16, values),
@@ -112,23 +177,33 @@ pub fn divide(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I16(_)) => divide_!(I16, values),
         &SExpr::Value(Value::I32(_)) => divide_!(I32, values),
         &SExpr::Value(Value::I64(_)) => divide_!(I64, values),
-        &SExpr::Value(Value::F32(_)) => divide_!(F32, values),
-        &SExpr::Value(Value::F64(_)) => divide_!(F64, values),
-        _ => Err(format!("Type cannot be divided: {:?}", values)),
+        &SExpr::Value(Value::F32(_)) => divide_!(float F32, values),
+        &SExpr::Value(Value::F64(_)) => divide_!(float F64, values),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be divided: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn inc(value: SExpr) -> Result<SExpr, String> {
-    let value = match value {
-        SExpr::Value(Value::U8(v)) => SExpr::Value(Value::U8(v + 1)),
-        SExpr::Value(Value::U16(v)) => SExpr::Value(Value::U16(v + 1)),
-        SExpr::Value(Value::U32(v)) => SExpr::Value(Value::U32(v + 1)),
-        SExpr::Value(Value::U64(v)) => SExpr::Value(Value::U64(v + 1)),
-        SExpr::Value(Value::I8(v)) => SExpr::Value(Value::I8(v + 1)),
-        SExpr::Value(Value::I16(v)) => SExpr::Value(Value::I16(v + 1)),
-        SExpr::Value(Value::I32(v)) => SExpr::Value(Value::I32(v + 1)),
-        SExpr::Value(Value::I64(v)) => SExpr::Value(Value::I64(v + 1)),
-        _ => return Err(format!("Type cannot be increased: {:?}", value)),
+pub fn inc(value: SExpr) -> Result<SExpr, DovahkiinError> {
+    let increased = match value {
+        SExpr::Value(Value::U8(v)) => v.checked_add(1).map(Value::U8),
+        SExpr::Value(Value::U16(v)) => v.checked_add(1).map(Value::U16),
+        SExpr::Value(Value::U32(v)) => v.checked_add(1).map(Value::U32),
+        SExpr::Value(Value::U64(v)) => v.checked_add(1).map(Value::U64),
+        SExpr::Value(Value::I8(v)) => v.checked_add(1).map(Value::I8),
+        SExpr::Value(Value::I16(v)) => v.checked_add(1).map(Value::I16),
+        SExpr::Value(Value::I32(v)) => v.checked_add(1).map(Value::I32),
+        SExpr::Value(Value::I64(v)) => v.checked_add(1).map(Value::I64),
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Type cannot be increased: {:?}",
+                value
+            )))
+        }
     };
-    Ok(value)
+    increased
+        .map(SExpr::Value)
+        .ok_or_else(|| overflow("Increment", &[value]))
 }
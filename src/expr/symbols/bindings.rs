@@ -1,90 +1,104 @@
 use super::super::Value;
 use super::*;
-use expr::interpreter::ENV;
-use std::collections::LinkedList;
-use std::rc::Rc;
+use expr::interpreter::{BindingScope, Frame, Scoping};
+use std::sync::Arc;
 
-pub fn bind_rc(id: u64, val_rc: Rc<SExpr>) {
-    ENV.with(|env| {
-        let env_borrow = env.borrow_mut();
-        let binding_map = &mut env_borrow.bindings.borrow_mut();
-        binding_map
-            .entry(id)
-            .or_insert_with(|| LinkedList::new())
-            .push_front(val_rc);
-    });
+// Validated bindings of a let form, and its body
+pub fn let_pairs(mut exprs: Vec<SExpr>) -> Result<(Vec<(u64, SExpr)>, Vec<SExpr>), DovahkiinError> {
+    if exprs.len() < 2 {
+        return Err(DovahkiinError::arity(format!(
+            "Too few parameters for let. Required at least 2 but found {}",
+            exprs.len()
+        )));
+    }
+    let form_expr = exprs.remove(0);
+    let form = if let SExpr::Vec(vec) = form_expr {
+        vec
+    } else {
+        return Err(DovahkiinError::type_error(format!(
+            "Let need a vector as form, found {:?}",
+            form_expr
+        )));
+    };
+    if form.len() % 2 == 1 {
+        return Err(DovahkiinError::arity(format!(
+            "Let form require even number of parameters, but found {}",
+            form.len()
+        )));
+    }
+    let mut pairs = Vec::with_capacity(form.len() / 2);
+    let mut form_iter = form.into_iter();
+    while let (Some(symbol), Some(expr)) = (form_iter.next(), form_iter.next()) {
+        let symbol_id = match symbol {
+            SExpr::Symbol(ref sym_str) => hash_str(sym_str),
+            SExpr::ISymbol(id, _) => id,
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "Cannot bind to {:?}, need symbol",
+                    symbol
+                )))
+            }
+        };
+        pairs.push((symbol_id, expr));
+    }
+    Ok((pairs, exprs))
 }
 
-pub fn bind(id: u64, val: SExpr) {
-    bind_rc(id, Rc::new(val))
+// Frame for the body of a lexically scoped let,
+// each value sees the bindings before it, the body sees all of them
+pub fn let_frame(
+    pairs: Vec<(u64, SExpr)>,
+    env: &Envorinment,
+) -> Result<Option<Arc<Frame>>, DovahkiinError> {
+    let parent = env.current_frame();
+    let mut frame = parent.clone();
+    let mut bindings = Vec::with_capacity(pairs.len());
+    for (symbol_id, expr) in pairs {
+        let value = env.with_frame(frame.clone(), || expr.eval(env))?;
+        bindings.push((symbol_id, Arc::new(value)));
+        frame = Some(Arc::new(Frame::new(bindings.clone(), parent.clone())));
+    }
+    Ok(frame)
 }
 
-pub fn unbind(id: u64) {
-    ENV.with(|env| {
-        let env_borrow = env.borrow_mut();
-        let binding_map = &mut env_borrow.bindings.borrow_mut();
-        binding_map
-            .entry(id)
-            .or_insert_with(|| LinkedList::new())
-            .pop_front();
-    });
+pub fn let_(exprs: Vec<SExpr>) -> Result<TailForm, DovahkiinError> {
+    let (pairs, body) = let_pairs(exprs)?;
+    Ok(TailForm::Let(pairs, body))
 }
 
-pub fn let_binding(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    if exprs.len() < 2 {
-        return Err(format!(
-            "Too few parameters for let. Required at least 2 but found {}",
-            exprs.len()
-        ));
-    }
-    let mut binded_ids = Vec::new();
-    {
-        let form_expr = exprs.remove(0);
-        let form = if let SExpr::Vec(vec) = form_expr {
-            vec
-        } else {
-            return Err(format!("Let need a vector as form, found {:?}", form_expr));
-        };
-        if form.len() % 2 == 1 {
-            return Err(format!(
-                "Let form require even number of parameters, but found {}",
-                form.len()
-            ));
+pub fn let_binding(
+    pairs: Vec<(u64, SExpr)>,
+    exprs: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    match env.scoping() {
+        Scoping::Lexical => {
+            let frame = let_frame(pairs, env)?;
+            env.with_frame(frame, || misc::do_(exprs, env))
         }
-        let mut form_iter = form.into_iter();
-        while let Some(symbol) = form_iter.next() {
-            let symbol_id = match symbol {
-                SExpr::Symbol(ref sym_str) => hash_str(sym_str),
-                SExpr::ISymbol(id, _) => id,
-                _ => return Err(format!("Cannot bind to {:?}, need symbol", symbol)),
-            };
-            if let Some(expr) = form_iter.next() {
-                bind(symbol_id, expr.eval()?);
-                binded_ids.push(symbol_id);
-            } else {
-                return Err(format!("cannot bind to {:?}, no value", symbol));
+        Scoping::Dynamic => {
+            let mut scope = BindingScope::new(env);
+            for (symbol_id, expr) in pairs {
+                let value = expr.eval(env)?;
+                scope.bind(symbol_id, value);
             }
+            misc::do_(exprs, env)
         }
     }
-    let mut body_result = SExpr::Value(Value::Null);
-    for body_line in exprs {
-        body_result = body_line.eval()?;
-    }
-    for binded_id in binded_ids {
-        unbind(binded_id);
-    }
-    return Ok(body_result);
 }
 
-pub fn define(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn define(mut exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     let name = exprs.remove(0);
-    let val = exprs.remove(0).eval()?;
+    let val = exprs.remove(0).eval(env)?;
     if let SExpr::Symbol(name) = name {
-        bind(hash_str(&name), val);
+        env.bind(hash_str(&name), val);
     } else if let SExpr::ISymbol(id, _) = name {
-        bind(id, val)
+        env.bind(id, val)
     } else {
-        return Err(format!("Cannot bind to {:?}", name));
+        return Err(DovahkiinError::type_error(format!(
+            "Cannot bind to {:?}",
+            name
+        )));
     }
     return Ok(SExpr::Value(Value::Null));
 }
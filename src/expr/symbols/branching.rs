@@ -1,51 +1,35 @@
 use super::utils::is_true;
 use super::*;
-pub fn if_(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+
+// Branch of if, if-not, when and when-not taken for the tester, None when there is no branch
+pub fn branch(
+    exprs: Vec<SExpr>,
+    expected: bool,
+    env: &Envorinment,
+) -> Result<Option<SExpr>, DovahkiinError> {
     let mut iter = exprs.into_iter();
     let tester = iter.next().unwrap();
     let then_expr = iter.next().unwrap();
     let else_expr = iter.next();
-    if is_true(tester.eval()?) {
-        return then_expr.eval();
-    } else if let Some(else_expr) = else_expr {
-        return else_expr.eval();
+    if is_true(tester.eval(env)?) == expected {
+        return Ok(Some(then_expr));
     } else {
-        return Ok(SExpr::Value(Value::Null));
+        return Ok(else_expr);
     }
 }
 
-pub fn if_not(exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    let mut iter = exprs.into_iter();
-    let tester = iter.next().unwrap();
-    let then_expr = iter.next().unwrap();
-    let else_expr = iter.next();
-    if !is_true(tester.eval()?) {
-        return then_expr.eval();
-    } else if let Some(else_expr) = else_expr {
-        return else_expr.eval();
-    } else {
-        return Ok(SExpr::Value(Value::Null));
-    }
+pub fn if_(exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+    Ok(TailForm::branch(branch(exprs, true, env)?))
 }
 
-pub fn when(exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    let mut iter = exprs.into_iter();
-    let tester = iter.next().unwrap();
-    let then_expr = iter.next().unwrap();
-    if is_true(tester.eval()?) {
-        return then_expr.eval();
-    } else {
-        return Ok(SExpr::Value(Value::Null));
-    }
+pub fn if_not(exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+    Ok(TailForm::branch(branch(exprs, false, env)?))
 }
 
-pub fn when_not(exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    let mut iter = exprs.into_iter();
-    let tester = iter.next().unwrap();
-    let then_expr = iter.next().unwrap();
-    if !is_true(tester.eval()?) {
-        return then_expr.eval();
-    } else {
-        return Ok(SExpr::Value(Value::Null));
-    }
+pub fn when(exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+    Ok(TailForm::branch(branch(exprs, true, env)?))
+}
+
+pub fn when_not(exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+    Ok(TailForm::branch(branch(exprs, false, env)?))
 }
@@ -1,8 +1,9 @@
 use super::*;
+use expr::interpreter::{map_size, vec_size};
 use std::collections::HashMap;
 use types::custom_types::owned_map::OwnedMap;
 
-pub fn size_(vals: &Vec<SExpr>) -> Result<u64, String> {
+pub fn size_(vals: &Vec<SExpr>) -> Result<u64, DovahkiinError> {
     let mut result: u64 = 0;
     for val in vals {
         result += match val {
@@ -10,53 +11,89 @@ pub fn size_(vals: &Vec<SExpr>) -> Result<u64, String> {
             &SExpr::Value(Value::Array(ref a)) => a.len(),
             &SExpr::Value(Value::String(ref s)) => s.len(),
             &SExpr::Value(Value::Map(ref m)) => m.len(),
-            _ => return Err(format!("Cannot measure size for {:?}", val)),
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "Cannot measure size for {:?}",
+                    val
+                )))
+            }
         } as u64;
     }
     return Ok(result);
 }
 
-pub fn size(vals: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn size(vals: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     Ok(SExpr::Value(Value::U64(size_(&vals)?)))
 }
 
-pub fn concat(lists: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn concat(lists: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     let total_size = size_(&lists)?;
+    env.allocate(vec_size(total_size as usize))?;
     let mut result = Vec::with_capacity(total_size as usize);
-    let mut vec_lists = Vec::new();
     for list in lists {
-        vec_lists.push(if let SExpr::Vec(v) = stream::to_vec(list)? {
-            v
-        } else {
-            return Err("Unexpected error on concat".to_string());
-        });
-    }
-    for mut vec in vec_lists {
-        result.append(&mut vec);
+        match list {
+            SExpr::Vec(mut vec) => result.append(&mut vec),
+            SExpr::Value(Value::Array(array)) => {
+                result.extend(array.into_iter().map(|val| SExpr::Value(val)))
+            }
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "Only vectors and arrays can be concatenated, found {:?}",
+                    list
+                )))
+            }
+        }
     }
     return Ok(SExpr::Vec(result));
 }
 
-pub fn hashmap(exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    if exprs.len() & 2 == 0 {
-        return Err(format!(
+pub fn hashmap(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    if exprs.len() % 2 != 0 {
+        return Err(DovahkiinError::arity(format!(
             "Map require even number of parameters. Found {}",
             exprs.len()
-        ));
+        )));
     }
+    let fields = exprs.iter().step_by(2).filter_map(|k| match k {
+        &SExpr::Value(Value::String(ref k_str)) | &SExpr::Keyword(_, ref k_str) => {
+            Some(k_str.as_str())
+        }
+        _ => None,
+    });
+    env.allocate(map_size(fields))?;
     let mut exprs = exprs.into_iter();
     let mut hashmap = HashMap::new();
     while let (Some(k), Some(v)) = (exprs.next(), exprs.next()) {
-        if let (SExpr::Value(Value::String(k_str)), SExpr::Value(value)) = (k, v) {
-            hashmap.insert(k_str, value);
-        } else {
-            return Err(format!("Wrong hashmap key value data type. Key should be a string and value should be a value"));
+        let v = match v {
+            SExpr::Vec(_) => stream::to_array(v, env)?,
+            _ => v,
+        };
+        match (k, v) {
+            (SExpr::Value(Value::String(k_str)), SExpr::Value(value))
+            | (SExpr::Keyword(_, k_str), SExpr::Value(value)) => {
+                hashmap.insert(k_str, value);
+            }
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "Wrong hashmap key value data type. \
+                     Key should be a string or keyword and value should be a value"
+                )))
+            }
         }
     }
     return Ok(SExpr::Value(Value::Map(OwnedMap::from_hash_map(hashmap))));
 }
 
-pub fn merge(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn merge(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    env.allocate(
+        exprs
+            .iter()
+            .map(|expr| match expr {
+                &SExpr::Value(Value::Map(ref m)) => map_size(m.fields.iter().map(|f| f.as_str())),
+                _ => 0,
+            })
+            .sum(),
+    )?;
     let mut value_map = HashMap::new();
     let mut field_names = Vec::new();
     for expr in exprs {
@@ -69,7 +106,12 @@ pub fn merge(exprs: Vec<SExpr>) -> Result<SExpr, String> {
                 }
                 field_names.append(&mut fields);
             }
-            _ => return Err(format!("Only map value can be merged. Found {:?}", expr)),
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "Only map value can be merged. Found {:?}",
+                    expr
+                )))
+            }
         }
     }
     field_names.dedup();
@@ -79,12 +121,18 @@ pub fn merge(exprs: Vec<SExpr>) -> Result<SExpr, String> {
     })))
 }
 
-pub fn conj(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    let list = stream::to_vec(exprs.remove(0));
-    if let Ok(SExpr::Vec(mut vec)) = list {
-        vec.append(&mut exprs);
-        return Ok(SExpr::Vec(vec));
-    } else {
-        return Err(format!("Cannot concat. {:?}", list));
+pub fn conj(mut exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    match stream::to_vec(exprs.remove(0), env)? {
+        SExpr::Vec(mut vec) => {
+            env.allocate(vec_size(exprs.len()))?;
+            vec.append(&mut exprs);
+            return Ok(SExpr::Vec(vec));
+        }
+        list => {
+            return Err(DovahkiinError::type_error(format!(
+                "Cannot conj to {:?}",
+                list
+            )))
+        }
     }
 }
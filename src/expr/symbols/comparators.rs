@@ -1,6 +1,6 @@
 use super::*;
 
-pub fn equals(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn equals(mut exprs: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     let last = exprs.pop().unwrap();
     for expr in exprs {
         if expr != last {
@@ -10,8 +10,8 @@ pub fn equals(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
     return Ok(SExpr::Value(Value::Bool(true)));
 }
 
-pub fn not_equals(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
-    return Ok(SExpr::Value(Value::Bool(exprs.pop() == exprs.pop())));
+pub fn not_equals(mut exprs: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
+    return Ok(SExpr::Value(Value::Bool(exprs.pop() != exprs.pop())));
 }
 
 macro_rules! reduce {
@@ -27,23 +27,25 @@ macro_rules! reduce {
                             return Ok(SExpr::Value(Value::Bool(false)));
                         }
                     } else {
-                        return Err(format!(
+                        return Err(DovahkiinError::type_error(format!(
                             "Type not match, expect {} found {:?}",
                             stringify!($type),
                             val
-                        ));
+                        )));
                     }
                 }
                 Ok(SExpr::Value(Value::Bool(true)))
             } else {
-                Err(format!(
+                Err(DovahkiinError::type_error(format!(
                     "Type not match on the first value, expect {} found {:?}",
                     stringify!($type),
                     first
-                ))
+                )))
             }
         } else {
-            Err("Cannot do reduce on values".to_string())
+            Err(DovahkiinError::arity(
+                "Cannot do reduce on values".to_string(),
+            ))
         }
     }};
 }
@@ -72,7 +74,7 @@ macro_rules! gte_ {
     }};
 }
 
-pub fn lt(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn lt(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => lt_!(U8, values),
         &SExpr::Value(Value::U16(_)) => lt_!(U16, values),
@@ -84,11 +86,14 @@ pub fn lt(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I64(_)) => lt_!(I64, values),
         &SExpr::Value(Value::F32(_)) => lt_!(F32, values),
         &SExpr::Value(Value::F64(_)) => lt_!(F64, values),
-        _ => Err(format!("Type cannot be compared: {:?}", values)),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be compared: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn lte(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn lte(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => lte_!(U8, values),
         &SExpr::Value(Value::U16(_)) => lte_!(U16, values),
@@ -100,11 +105,14 @@ pub fn lte(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I64(_)) => lte_!(I64, values),
         &SExpr::Value(Value::F32(_)) => lte_!(F32, values),
         &SExpr::Value(Value::F64(_)) => lte_!(F64, values),
-        _ => Err(format!("Type cannot be compared: {:?}", values)),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be compared: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn gt(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn gt(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => gt_!(U8, values),
         &SExpr::Value(Value::U16(_)) => gt_!(U16, values),
@@ -116,11 +124,14 @@ pub fn gt(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I64(_)) => gt_!(I64, values),
         &SExpr::Value(Value::F32(_)) => gt_!(F32, values),
         &SExpr::Value(Value::F64(_)) => gt_!(F64, values),
-        _ => Err(format!("Type cannot be compared: {:?}", values)),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be compared: {:?}",
+            values
+        ))),
     }
 }
 
-pub fn gte(values: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn gte(values: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     match values.get(0).unwrap() {
         &SExpr::Value(Value::U8(_)) => gte_!(U8, values),
         &SExpr::Value(Value::U16(_)) => gte_!(U16, values),
@@ -132,6 +143,9 @@ pub fn gte(values: Vec<SExpr>) -> Result<SExpr, String> {
         &SExpr::Value(Value::I64(_)) => gte_!(I64, values),
         &SExpr::Value(Value::F32(_)) => gte_!(F32, values),
         &SExpr::Value(Value::F64(_)) => gte_!(F64, values),
-        _ => Err(format!("Type cannot be compared: {:?}", values)),
+        _ => Err(DovahkiinError::type_error(format!(
+            "Type cannot be compared: {:?}",
+            values
+        ))),
     }
 }
@@ -0,0 +1,187 @@
+use super::*;
+use error::ErrorKind;
+use expr::interpreter::{map_size, value_size, BindingScope, Frame, Scoping};
+use printer::lisp::print_value;
+use std::sync::Arc;
+use types::OwnedMap;
+
+// Errors are caught as maps of their :message, :kind and :data.
+// ex-info builds the same map, so thrown and native errors are handled alike.
+
+const MESSAGE_KEY: &str = "message";
+const KIND_KEY: &str = "kind";
+const DATA_KEY: &str = "data";
+
+pub fn ex_info(message: SExpr, data: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    match (message, data) {
+        (SExpr::Value(Value::String(message)), SExpr::Value(data @ Value::Map(_)))
+        | (SExpr::Value(Value::String(message)), SExpr::Value(data @ Value::Null)) => {
+            env.allocate(map_size([MESSAGE_KEY, DATA_KEY].iter().cloned()))?;
+            let mut info = OwnedMap::new();
+            info.insert(MESSAGE_KEY, Value::String(message));
+            info.insert(DATA_KEY, data);
+            Ok(SExpr::Value(Value::Map(info)))
+        }
+        (message, data) => Err(DovahkiinError::type_error(format!(
+            "ex-info need a message string and a data map, found {:?} and {:?}",
+            message, data
+        ))),
+    }
+}
+
+// Message and data of an ex-info map, None for other values
+fn info_parts(value: &Value) -> Option<(String, Value)> {
+    if let &Value::Map(ref info) = value {
+        if let &Value::String(ref message) = info.get(MESSAGE_KEY) {
+            return Some((message.clone(), info.get(DATA_KEY).clone()));
+        }
+    }
+    None
+}
+
+/// Throws an ex-info map with its message and data. Strings are thrown as the message,
+/// other values as the data.
+pub fn throw(value: SExpr) -> Result<SExpr, DovahkiinError> {
+    let value = match value {
+        SExpr::Value(value) => value,
+        value => {
+            return Err(DovahkiinError::type_error(format!(
+                "Only values can be thrown, found {:?}",
+                value
+            )))
+        }
+    };
+    let (message, data) = match info_parts(&value) {
+        Some(parts) => parts,
+        None => match value {
+            Value::String(message) => (message, Value::Null),
+            value => (format!("Thrown {}", print_value(&value)), value),
+        },
+    };
+    Err(DovahkiinError::user(message, data))
+}
+
+// The error as the catch form sees it. Wrapped errors are caught by their root cause,
+// like the ex-info thrown inside a function.
+fn error_value(error: &DovahkiinError, env: &Envorinment) -> Result<Value, DovahkiinError> {
+    let error = error.root_cause();
+    let kind = error.kind.to_string();
+    let data = error.payload.clone().unwrap_or(Value::Null);
+    // the message and data are copied out of the error
+    env.allocate(
+        map_size([MESSAGE_KEY, KIND_KEY, DATA_KEY].iter().cloned())
+            + error.message.len()
+            + kind.len()
+            + value_size(&data),
+    )?;
+    let mut info = OwnedMap::new();
+    info.insert(MESSAGE_KEY, Value::String(error.message.clone()));
+    info.insert(KIND_KEY, Value::String(kind));
+    info.insert(DATA_KEY, data);
+    Ok(Value::Map(info))
+}
+
+// Head symbol name of a (catch ...) or (finally ...) form
+fn clause_name(expr: &SExpr) -> Option<&str> {
+    if let &SExpr::List(ref items) = expr.unlocated() {
+        match items.first().map(|head| head.unlocated()) {
+            Some(&SExpr::ISymbol(_, ref name)) | Some(&SExpr::Symbol(ref name))
+                if name == "catch" || name == "finally" =>
+            {
+                return Some(name);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Forms after the head of a clause
+fn clause_items(expr: SExpr) -> Vec<SExpr> {
+    match expr {
+        SExpr::Located(_, expr) => clause_items(*expr),
+        SExpr::List(items) => items.into_iter().skip(1).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn symbol_id(expr: &SExpr) -> Option<u64> {
+    match expr.unlocated() {
+        &SExpr::ISymbol(id, _) => Some(id),
+        &SExpr::Symbol(ref name) => Some(hash_str(name)),
+        _ => None,
+    }
+}
+
+fn handle(
+    symbol_id: u64,
+    error: &DovahkiinError,
+    handler: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    let error = SExpr::Value(error_value(error, env)?);
+    match env.scoping() {
+        Scoping::Lexical => {
+            let frame = Frame::new(vec![(symbol_id, Arc::new(error))], env.current_frame());
+            env.with_frame(Some(Arc::new(frame)), || misc::do_(handler, env))
+        }
+        Scoping::Dynamic => {
+            let mut scope = BindingScope::new(env);
+            scope.bind(symbol_id, error);
+            misc::do_(handler, env)
+        }
+    }
+}
+
+/// (try body... (catch e handler...) (finally cleanup...)), both clauses are optional.
+/// Limit errors are not caught, so a query cannot keep running past its budget.
+pub fn try_(mut exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    let clauses_start = exprs
+        .iter()
+        .position(|expr| clause_name(expr).is_some())
+        .unwrap_or(exprs.len());
+    let clauses = exprs.split_off(clauses_start);
+    let mut catch = None;
+    let mut finally = None;
+    for clause in clauses {
+        // Some(true) for catch, Some(false) for finally, None for other forms
+        let is_catch = clause_name(&clause).map(|name| name == "catch");
+        match (is_catch, &catch, &finally) {
+            (Some(true), &None, &None) => {
+                let mut items = clause_items(clause);
+                let id = match items.first().and_then(symbol_id) {
+                    Some(id) => id,
+                    None => {
+                        return Err(DovahkiinError::type_error(format!(
+                            "catch need a symbol to bind the error, found {:?}",
+                            items.first()
+                        )))
+                    }
+                };
+                items.remove(0);
+                catch = Some((id, items));
+            }
+            (Some(false), _, &None) => finally = Some(clause_items(clause)),
+            _ => {
+                return Err(DovahkiinError::runtime(format!(
+                    "try can only end with one catch and then one finally, found {:?}",
+                    clause
+                )))
+            }
+        }
+    }
+    let result = match (misc::do_(exprs, env), catch) {
+        (Err(error), Some((id, handler))) => {
+            if error.kind == ErrorKind::LimitExceeded {
+                Err(error)
+            } else {
+                handle(id, &error, handler, env)
+            }
+        }
+        (result, _) => result,
+    };
+    if let Some(cleanup) = finally {
+        misc::do_(cleanup, env)?;
+    }
+    result
+}
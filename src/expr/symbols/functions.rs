@@ -1,82 +1,161 @@
-use super::super::interpreter::ENV;
-use super::bindings::bind;
+use super::super::interpreter::eval_all;
 use super::lambda::{eval_lambda, lambda_placeholder};
+use super::num_types::type_literals;
+use super::stream::to_vec;
 use super::*;
-use std::borrow::Borrow;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub fn eval_function(func_expr: &SExpr, params: Vec<SExpr>) -> Result<SExpr, String> {
+/// Calls the function with the argument forms of a call. The arguments are evaluated
+/// here, once, unless the function is a macro.
+pub fn eval_function(
+    func_expr: &SExpr,
+    params: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    let args = match native_symbol(func_expr, env) {
+        Some(ref symbol) if symbol.is_macro() => params,
+        // literals are left to be typed with the other operands in `apply_function`
+        Some(_) => {
+            let mut args = Vec::with_capacity(params.len());
+            for param in params {
+                args.push(match param {
+                    SExpr::Untyped(_) => param,
+                    param => param.eval(env)?,
+                });
+            }
+            args
+        }
+        None => eval_all(params, env)?,
+    };
+    apply_function(func_expr, args, env)
+}
+
+// Native symbol the function resolves to, None for lambdas and values
+fn native_symbol(func_expr: &SExpr, env: &Envorinment) -> Option<Arc<dyn Symbol>> {
+    let symbol_id = match func_expr {
+        &SExpr::ISymbol(symbol_id, _) => symbol_id,
+        &SExpr::Symbol(ref symbol_name) => hash_str(symbol_name),
+        _ => return None,
+    };
+    match env.lookup(symbol_id) {
+        Some(env_bind) => native_symbol(&env_bind, env),
+        None => ISYMBOL_MAP.get(symbol_id),
+    }
+}
+
+// Number literals without unit get their default type, or the type of the other operands
+// for the symbols typing them
+fn type_args(symbol_id: u64, args: Vec<SExpr>) -> Result<Vec<SExpr>, DovahkiinError> {
+    let mut literals = Vec::new();
+    let mut typed = Vec::with_capacity(args.len());
+    for (i, arg) in args.into_iter().enumerate() {
+        typed.push(match arg {
+            SExpr::Untyped(value) => {
+                literals.push(i);
+                SExpr::Value(value)
+            }
+            arg => arg,
+        });
+    }
+    if !literals.is_empty() && LITERAL_TYPED_SYMBOLS.contains(&symbol_id) {
+        type_literals(&mut typed, &literals)?;
+    }
+    Ok(typed)
+}
+
+/// Calls the function with arguments which are already evaluated, like the items
+/// `map` and `filter` pass to it. Macros get the arguments as they are.
+pub fn apply_function(
+    func_expr: &SExpr,
+    params: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
     match func_expr {
         &SExpr::ISymbol(symbol_id, ref name) => {
-            let mut env_bind_ref: Option<Rc<SExpr>> = None;
-            ENV.with(|env| {
-                let env_borrowed = env.borrow();
-                let bindings = env_borrowed.get_mut_bindings();
-                env_bind_ref = if let Some(binding_list) = bindings.get(&symbol_id) {
-                    binding_list.front().cloned()
+            if let Some(env_bind) = env.lookup(symbol_id) {
+                if let SExpr::LAMBDA(..) = *env_bind {
+                    return eval_lambda(env_bind, params, env).map_err(|e| {
+                        DovahkiinError::wrap(e, format!("Error in function '{}'", name))
+                    });
                 } else {
-                    None
+                    return apply_function(&env_bind, params, env);
                 }
-            });
-            if let Some(env_bind) = env_bind_ref {
-                return eval_lambda(env_bind.borrow(), params);
             } else {
                 // internal functions
-                let symbols = ISYMBOL_MAP.map.borrow();
-                match symbols.get(&symbol_id) {
+                match ISYMBOL_MAP.get(symbol_id) {
                     Some(symbol) => {
-                        // if the symbol is not a macro, parameters will all be evaled here. Or passthrough those expressions.
-                        return symbol.eval(if symbol.is_macro() {
+                        let params = if symbol.is_macro() {
                             params
                         } else {
-                            let mut evaled_params = Vec::with_capacity(params.len());
-                            for param in params {
-                                evaled_params.push(param.eval()?);
-                            }
-                            evaled_params
-                        });
+                            type_args(symbol_id, params)?
+                        };
+                        return symbol.eval(params, env);
                     }
                     _ => {
-                        return Err(format!(
+                        return Err(DovahkiinError::unknown_symbol(format!(
                             "Cannot find symbol \'{}\', id: {}",
                             name, symbol_id
-                        ))
+                        )))
                     }
                 }
             }
         }
         &SExpr::Symbol(ref symbol_name) => {
-            return eval_function(
+            return apply_function(
                 &SExpr::ISymbol(hash_str(symbol_name), symbol_name.clone()),
                 params,
+                env,
             )
         }
-        &SExpr::LAMBDA(_, _) => return eval_lambda(func_expr, params),
+        &SExpr::LAMBDA(..) => return eval_lambda(Arc::new(func_expr.clone()), params, env),
+        &SExpr::Keyword(key_id, ref name) => {
+            // (:key map)
+            if params.len() != 1 {
+                return Err(DovahkiinError::arity(format!(
+                    "get by keyword :{} take exactly one parameter, found {}",
+                    name,
+                    params.len()
+                )));
+            }
+            match params.get(0) {
+                Some(&SExpr::Value(Value::Map(ref m))) => {
+                    return Ok(SExpr::Value(m.get_by_key_id(key_id).clone()))
+                }
+                Some(&SExpr::Value(Value::Null)) => return Ok(SExpr::Value(Value::Null)),
+                _ => {
+                    return Err(DovahkiinError::type_error(format!(
+                        "When use keyword :{} as function, \
+                         only map parameter is accepted, found {:?}",
+                        name, params
+                    )))
+                }
+            }
+        }
         &SExpr::Value(Value::String(ref str_key)) => {
             // same as clojure (:key map)
             if params.len() > 1 {
-                return Err(format!(
+                return Err(DovahkiinError::arity(format!(
                     "get from map can only take one parameter, found {}",
                     params.len()
-                ));
+                )));
             }
             if let Some(&SExpr::Value(Value::Map(ref m))) = params.get(0) {
                 return Ok(SExpr::Value(m.get(str_key).clone()));
             } else {
-                return Err(format!(
+                return Err(DovahkiinError::type_error(format!(
                     "When use string value as function, \
                      only one map parameter is accepted, found {:?}",
                     params
-                ));
+                )));
             }
         }
         &SExpr::Value(Value::U64(index)) => {
             // get element by index from vec or by key_id form map
             if params.len() > 1 {
-                return Err(format!(
+                return Err(DovahkiinError::arity(format!(
                     "get by index/id can only take one parameter, found {}",
                     params.len()
-                ));
+                )));
             }
             match params.get(0) {
                 Some(&SExpr::Value(Value::Map(ref m))) => {
@@ -87,38 +166,43 @@ pub fn eval_function(func_expr: &SExpr, params: Vec<SExpr>) -> Result<SExpr, Str
                         arr.get(index as usize).cloned().unwrap_or(Value::Null),
                     ))
                 }
-                _ => return Err(format!("Data type not accepted for {:?}", params)),
+                _ => {
+                    return Err(DovahkiinError::type_error(format!(
+                        "Data type not accepted for {:?}",
+                        params
+                    )))
+                }
             }
         }
         &SExpr::Value(Value::Map(ref m)) => {
             if params.len() > 1 {
-                return Err(format!(
+                return Err(DovahkiinError::arity(format!(
                     "get map can only take one parameter, found {}",
                     params.len()
-                ));
+                )));
             }
             match params.get(0) {
                 Some(&SExpr::Value(Value::String(ref str_key))) => {
                     return Ok(SExpr::Value(m.get(str_key).clone()))
                 }
-                Some(&SExpr::Value(Value::U64(key_id))) => {
+                Some(&SExpr::Value(Value::U64(key_id))) | Some(&SExpr::Keyword(key_id, _)) => {
                     return Ok(SExpr::Value(m.get_by_key_id(key_id).clone()))
                 }
                 _ => {
-                    return Err(format!(
-                        "Key format not accepted, expect one string or u64\
+                    return Err(DovahkiinError::type_error(format!(
+                        "Key format not accepted, expect one string, keyword or u64\
                          Found {:?}",
                         params
-                    ));
+                    )));
                 }
             }
         }
         &SExpr::Value(Value::Array(ref array)) => {
             if params.len() > 1 {
-                return Err(format!(
+                return Err(DovahkiinError::arity(format!(
                     "get map can only take one parameter, found {}",
                     params.len()
-                ));
+                )));
             }
             match params.get(0) {
                 Some(&SExpr::Value(Value::U64(key_id))) => {
@@ -127,30 +211,53 @@ pub fn eval_function(func_expr: &SExpr, params: Vec<SExpr>) -> Result<SExpr, Str
                     ))
                 }
                 _ => {
-                    return Err(format!(
+                    return Err(DovahkiinError::type_error(format!(
                         "Index format not accepted, expect u64\
                          Found {:?}",
                         params
-                    ));
+                    )));
                 }
             }
         }
-        _ => return Err(format!("{:?} is not a function", func_expr)),
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "{:?} is not a function",
+                func_expr
+            )))
+        }
     }
 }
 
-pub fn defn(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn defn(mut exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     let name = exprs.remove(0);
-    let lambda = lambda_placeholder(exprs)?;
+    let lambda = lambda_placeholder(exprs, env)?;
     if let SExpr::Symbol(name) = name {
-        bind(hash_str(&name), lambda);
+        env.bind(hash_str(&name), lambda);
     } else if let SExpr::ISymbol(id, _) = name {
-        bind(id, lambda);
+        env.bind(id, lambda);
     } else {
-        return Err(format!(
+        return Err(DovahkiinError::type_error(format!(
             "Function name should be a symbol, found {:?}",
             name
-        ));
+        )));
     }
     return Ok(SExpr::Value(Value::Null));
 }
+
+/// (apply f args... coll), calls f with the arguments followed by the items of the collection.
+/// Items are evaluated once, like by `map`, and passed to f as they are.
+pub fn apply(mut exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    let func = exprs.remove(0);
+    let spread = match to_vec(exprs.pop().unwrap(), env)? {
+        SExpr::Vec(items) => items,
+        _ => unreachable!(),
+    };
+    for item in spread {
+        exprs.push(match item {
+            // typed with the other arguments, like literals in the call
+            SExpr::Untyped(_) => item,
+            item => item.eval(env)?,
+        });
+    }
+    apply_function(&func, exprs, env)
+}
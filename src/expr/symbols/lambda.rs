@@ -1,6 +1,13 @@
 use super::bindings::*;
+use super::functions::eval_function;
 use super::*;
-pub fn lambda_placeholder(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
+use expr::interpreter::{eval_all, BindingScope, Captured, Frame, Scoping};
+use std::sync::Arc;
+
+pub fn lambda_placeholder(
+    mut exprs: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
     let params = exprs.remove(0);
     let params_list = if let SExpr::Vec(symbols) = params {
         let mut list = Vec::new();
@@ -9,51 +16,265 @@ pub fn lambda_placeholder(mut exprs: Vec<SExpr>) -> Result<SExpr, String> {
                 SExpr::Symbol(name) => SExpr::ISymbol(hash_str(&name), name),
                 SExpr::ISymbol(id, name) => SExpr::ISymbol(id, name),
                 _ => {
-                    return Err(format!(
+                    return Err(DovahkiinError::type_error(format!(
                         "lambda can only bind to symbols, found {:?}",
                         symbol
-                    ))
+                    )))
                 }
             });
         }
+        check_rest_param(&list)?;
         list
     } else {
-        return Err(format!("lambda form should be vector, found {:?}", params));
+        return Err(DovahkiinError::type_error(format!(
+            "lambda form should be vector, found {:?}",
+            params
+        )));
     };
-    Ok(SExpr::LAMBDA(params_list, exprs))
-}
-
-pub fn eval_lambda(lambda_expr: &SExpr, params: Vec<SExpr>) -> Result<SExpr, String> {
-    if let &SExpr::LAMBDA(ref params_list, ref body) = lambda_expr {
-        {
-            // bind parameters
-            let mut param_pos = 0;
-            for param in params {
-                let lambda_param = params_list.get(param_pos).unwrap();
-                if let &SExpr::ISymbol(id, _) = lambda_param {
-                    bind(id, param);
-                } else {
-                    return Err(format!(
-                        "Expect ISymbol for lambda form, found {:?}",
-                        lambda_param
-                    ));
+    Ok(SExpr::LAMBDA(
+        params_list,
+        exprs,
+        Captured(env.current_frame()),
+    ))
+}
+
+// Result of an expression in tail position. Calls are returned to the trampoline in
+// `call_lambda` instead of growing the stack.
+enum Tail {
+    Value(SExpr),
+    // lambda, evaluated arguments and the name it was called by
+    Call(Arc<SExpr>, Vec<SExpr>, Option<String>),
+}
+
+// Evaluates the rest of a special form in tail position. With dynamic scoping the bindings
+// of a let are made in the scope of the running call.
+fn form_tail(
+    form: TailForm,
+    scope: &mut BindingScope,
+    env: &Envorinment,
+) -> Result<Tail, DovahkiinError> {
+    match form {
+        TailForm::Value(value) => Ok(Tail::Value(value)),
+        TailForm::Expr(expr) => eval_tail(expr, scope, env),
+        TailForm::Body(body) => body_tail(body, scope, env),
+        TailForm::Let(pairs, body) => match env.scoping() {
+            Scoping::Lexical => {
+                let frame = let_frame(pairs, env)?;
+                env.with_frame(frame, || body_tail(body, scope, env))
+            }
+            Scoping::Dynamic => {
+                for (symbol_id, expr) in pairs {
+                    let value = expr.eval(env)?;
+                    scope.bind(symbol_id, value);
                 }
-                param_pos += 1;
+                body_tail(body, scope, env)
             }
+        },
+    }
+}
+
+// Evaluates the body, the last expression in tail position
+fn body_tail(
+    mut body: Vec<SExpr>,
+    scope: &mut BindingScope,
+    env: &Envorinment,
+) -> Result<Tail, DovahkiinError> {
+    let last = match body.pop() {
+        Some(last) => last,
+        None => return Ok(Tail::Value(SExpr::Value(Value::Null))),
+    };
+    for expr in body {
+        expr.eval(env)?;
+    }
+    eval_tail(last, scope, env)
+}
+
+// Evaluates like `SExpr::eval`, but defers lambda calls. Special forms are evaluated up to
+// their tail position by `Symbol::eval_tail`, and followed there.
+fn eval_tail(
+    expr: SExpr,
+    scope: &mut BindingScope,
+    env: &Envorinment,
+) -> Result<Tail, DovahkiinError> {
+    // one step for each expression, as `SExpr::eval` counts them
+    let exprs = match expr {
+        SExpr::Located(span, expr) => {
+            env.step()?;
+            return eval_tail(*expr, scope, env).map_err(|e| e.or_span(span));
         }
-        let mut last_result = SExpr::Value(Value::Null);
-        for body_line in body {
-            // eval function body by cloning expression
-            last_result = body_line.clone().eval()?;
+        SExpr::List(exprs) => {
+            env.step()?;
+            exprs
         }
-        for lambda_param in params_list {
-            // unbind parameters
-            if let &SExpr::ISymbol(id, _) = lambda_param {
-                unbind(id);
+        expr => return Ok(Tail::Value(expr.eval(env)?)),
+    };
+    if exprs.is_empty() {
+        return Ok(Tail::Value(SExpr::Value(Value::Null)));
+    }
+    let mut iter = exprs.into_iter();
+    let head = iter.next().unwrap();
+    let params: Vec<SExpr> = iter.collect();
+    let func = match head {
+        SExpr::ISymbol(symbol_id, name) => match env.lookup(symbol_id) {
+            Some(bound) => {
+                if let SExpr::LAMBDA(..) = *bound {
+                    return Ok(Tail::Call(bound, eval_all(params, env)?, Some(name)));
+                }
+                SExpr::ISymbol(symbol_id, name)
             }
+            None => match ISYMBOL_MAP.get(symbol_id) {
+                Some(ref symbol) if symbol.is_macro() => {
+                    return form_tail(symbol.eval_tail(params, env)?, scope, env)
+                }
+                _ => SExpr::ISymbol(symbol_id, name),
+            },
+        },
+        head => head.eval(env)?,
+    };
+    if let SExpr::LAMBDA(..) = func {
+        return Ok(Tail::Call(Arc::new(func), eval_all(params, env)?, None));
+    }
+    Ok(Tail::Value(eval_function(&func, params, env)?))
+}
+
+const REST_MARKER: &str = "&";
+
+fn is_rest_marker(param: &SExpr) -> bool {
+    match param {
+        &SExpr::ISymbol(_, ref name) => name == REST_MARKER,
+        _ => false,
+    }
+}
+
+// [x y & rest], the marker can only be followed by one symbol for the rest of the arguments
+fn check_rest_param(params_list: &Vec<SExpr>) -> Result<(), DovahkiinError> {
+    match params_list.iter().position(is_rest_marker) {
+        Some(pos) if pos + 2 != params_list.len() || is_rest_marker(&params_list[pos + 1]) => {
+            Err(DovahkiinError::type_error(format!(
+                "{} should be followed by one symbol for the rest parameters, found {:?}",
+                REST_MARKER,
+                &params_list[pos + 1..]
+            )))
         }
-        Ok(last_result)
+        _ => Ok(()),
+    }
+}
+
+fn param_id(lambda_param: &SExpr) -> Result<u64, DovahkiinError> {
+    if let &SExpr::ISymbol(id, _) = lambda_param {
+        Ok(id)
     } else {
-        return Err(format!("Expect lambda expression, found {:?}", lambda_expr));
+        Err(DovahkiinError::type_error(format!(
+            "Expect ISymbol for lambda form, found {:?}",
+            lambda_param
+        )))
+    }
+}
+
+// Pairs the parameters with the arguments, the rest parameter gets a vector of
+// the arguments left, which is empty when there is none.
+fn bind_params(
+    params_list: &Vec<SExpr>,
+    params: Vec<SExpr>,
+) -> Result<Vec<(u64, SExpr)>, DovahkiinError> {
+    let (fixed, rest) = match params_list.iter().position(is_rest_marker) {
+        Some(pos) => (&params_list[..pos], params_list.get(pos + 1)),
+        None => (&params_list[..], None),
+    };
+    if params.len() < fixed.len() || (rest.is_none() && params.len() > fixed.len()) {
+        return Err(DovahkiinError::arity(format!(
+            "Function expect {}{} parameters but found {}",
+            if rest.is_some() { "at least " } else { "" },
+            fixed.len(),
+            params.len()
+        )));
+    }
+    // literals passed through by apply get their default type
+    let mut params: Vec<SExpr> = params
+        .into_iter()
+        .map(|param| match param {
+            SExpr::Untyped(value) => SExpr::Value(value),
+            param => param,
+        })
+        .collect();
+    let rest_params = params.split_off(fixed.len());
+    let mut bindings = Vec::with_capacity(params_list.len());
+    for (lambda_param, param) in fixed.iter().zip(params) {
+        bindings.push((param_id(lambda_param)?, param));
+    }
+    if let Some(rest) = rest {
+        bindings.push((param_id(rest)?, SExpr::Vec(rest_params)));
     }
+    Ok(bindings)
+}
+
+fn not_lambda(lambda_expr: &SExpr) -> DovahkiinError {
+    DovahkiinError::type_error(format!("Expect lambda expression, found {:?}", lambda_expr))
+}
+
+// Calls the lambda. Calls in tail position of the body are run by this loop, so recursion
+// in tail position does not grow the stack. With dynamic scoping the bindings of each call
+// are unbound before the next one is bound, so a call in tail position does not see the
+// bindings of its caller, and a tail loop does not grow the bindings either.
+fn call_lambda(
+    lambda_expr: Arc<SExpr>,
+    params: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    let mut lambda_expr = lambda_expr;
+    let mut params = params;
+    let mut name: Option<String> = None;
+    loop {
+        let mut scope = BindingScope::new(env);
+        let tail = match *lambda_expr {
+            SExpr::LAMBDA(ref params_list, ref body, ref captured) => {
+                bind_params(params_list, params).and_then(|bindings| match env.scoping() {
+                    Scoping::Lexical => {
+                        let bindings = bindings
+                            .into_iter()
+                            .map(|(id, param)| (id, Arc::new(param)))
+                            .collect();
+                        let frame = Frame::new(bindings, captured.0.clone());
+                        // eval function body by cloning expression
+                        env.with_frame(Some(Arc::new(frame)), || {
+                            body_tail(body.clone(), &mut scope, env)
+                        })
+                    }
+                    Scoping::Dynamic => {
+                        for (id, param) in bindings {
+                            scope.bind(id, param);
+                        }
+                        body_tail(body.clone(), &mut scope, env)
+                    }
+                })
+            }
+            _ => Err(not_lambda(&lambda_expr)),
+        };
+        let tail = match (tail, name) {
+            (Err(e), Some(name)) => {
+                return Err(DovahkiinError::wrap(
+                    e,
+                    format!("Error in function '{}'", name),
+                ))
+            }
+            (tail, _) => tail?,
+        };
+        match tail {
+            Tail::Value(value) => return Ok(value),
+            Tail::Call(next_lambda, next_params, next_name) => {
+                lambda_expr = next_lambda;
+                params = next_params;
+                name = next_name;
+            }
+        }
+    }
+}
+
+/// Calls the lambda with arguments evaluated by the caller
+pub fn eval_lambda(
+    lambda_expr: Arc<SExpr>,
+    params: Vec<SExpr>,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    env.with_call(|| call_lambda(lambda_expr, params, env))
 }
@@ -1,36 +1,45 @@
 use super::utils::is_true;
 use super::*;
 
-pub fn or(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn or(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     for expr in exprs {
-        if is_true(expr.eval()?) {
+        if is_true(expr.eval(env)?) {
             return Ok(SExpr::Value(Value::Bool(true)));
         }
     }
     return Ok(SExpr::Value(Value::Bool(false)));
 }
 
-pub fn and(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn and(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     for expr in exprs {
-        if !is_true(expr.eval()?) {
+        if !is_true(expr.eval(env)?) {
             return Ok(SExpr::Value(Value::Bool(false)));
         }
     }
     return Ok(SExpr::Value(Value::Bool(true)));
 }
 
-pub fn cond(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+// Expression of the first clause whose condition is true
+pub fn cond_branch(exprs: Vec<SExpr>, env: &Envorinment) -> Result<Option<SExpr>, DovahkiinError> {
     if exprs.len() % 2 == 1 {
-        return Err(format!(
+        return Err(DovahkiinError::arity(format!(
             "cond need even number of parameters, found {}",
             exprs.len()
-        ));
+        )));
     }
     let mut exprs = exprs.into_iter();
     while let (Some(condition), Some(expr)) = (exprs.next(), exprs.next()) {
-        if is_true(condition.eval()?) {
-            return expr.eval();
+        if is_true(condition.eval(env)?) {
+            return Ok(Some(expr));
         }
     }
-    return Ok(SExpr::Value(Value::Null));
+    return Ok(None);
+}
+
+pub fn cond(exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+    Ok(TailForm::branch(cond_branch(exprs, env)?))
+}
+
+pub fn not(expr: SExpr) -> Result<SExpr, DovahkiinError> {
+    Ok(SExpr::Value(Value::Bool(!is_true(expr))))
 }
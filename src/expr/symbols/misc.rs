@@ -1,9 +1,9 @@
 use super::*;
 
-pub fn do_(exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn do_(exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     let mut result = SExpr::Value(Value::Null);
     for expr in exprs {
-        result = expr.eval()?;
+        result = expr.eval(env)?;
     }
     return Ok(result);
 }
@@ -1,8 +1,10 @@
 use bifrost_hasher::hash_str;
+pub use error::DovahkiinError;
+use expr::interpreter::Envorinment;
 use expr::SExpr;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
 pub use types::OwnedValue as Value;
 
 mod arithmetic;
@@ -10,37 +12,84 @@ pub mod bindings;
 mod branching;
 mod collections;
 mod comparators;
+mod exceptions;
 pub mod functions;
 mod lambda;
 mod logic;
 pub mod misc;
 mod num_types;
+mod quoting;
 mod stream;
 pub mod utils;
 
-pub trait Symbol: Sync + Debug {
-    fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, String>;
+/// Native function or macro. The environment is the one of the interpreter evaluating it,
+/// to evaluate parameters of macros and to call back functions.
+pub trait Symbol: Send + Sync + Debug {
+    fn eval(&self, exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError>;
     fn is_macro(&self) -> bool;
+    /// Evaluates the form up to the expression in its tail position, which is left to
+    /// the caller. Special forms override it, other symbols are evaluated completely.
+    fn eval_tail(&self, exprs: Vec<SExpr>, env: &Envorinment) -> Result<TailForm, DovahkiinError> {
+        self.eval(exprs, env).map(TailForm::Value)
+    }
+}
+
+/// Rest of a special form in tail position, so lambda calls there can be run by the
+/// caller without growing the stack
+pub enum TailForm {
+    Value(SExpr),
+    // evaluated in place of the form
+    Expr(SExpr),
+    // evaluated in order, the last expression in tail position
+    Body(Vec<SExpr>),
+    // bindings of a let and its body
+    Let(Vec<(u64, SExpr)>, Vec<SExpr>),
 }
 
+impl TailForm {
+    // Branch taken by if, when or cond, null when there is none
+    pub fn branch(branch: Option<SExpr>) -> TailForm {
+        match branch {
+            Some(expr) => TailForm::Expr(expr),
+            None => TailForm::Value(SExpr::Value(Value::Null)),
+        }
+    }
+
+    pub fn eval(self, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+        match self {
+            TailForm::Value(value) => Ok(value),
+            TailForm::Expr(expr) => expr.eval(env),
+            TailForm::Body(body) => misc::do_(body, env),
+            TailForm::Let(pairs, body) => bindings::let_binding(pairs, body, env),
+        }
+    }
+}
+
+// Shared by all interpreters. Symbols are taken out of the lock before they are evaluated,
+// so symbols evaluating other symbols do not hold it.
 pub struct ISymbolMap {
-    pub map: RefCell<HashMap<u64, Box<dyn Symbol>>>,
+    pub map: RwLock<HashMap<u64, Arc<dyn Symbol>>>,
 }
 
-unsafe impl Sync for ISymbolMap {}
 impl ISymbolMap {
-    pub fn new(map: HashMap<u64, Box<dyn Symbol>>) -> ISymbolMap {
+    pub fn new(map: HashMap<u64, Arc<dyn Symbol>>) -> ISymbolMap {
         ISymbolMap {
-            map: RefCell::new(map),
+            map: RwLock::new(map),
         }
     }
+    pub fn get(&self, symbol_id: u64) -> Option<Arc<dyn Symbol>> {
+        self.map
+            .read()
+            .ok()
+            .and_then(|map| map.get(&symbol_id).cloned())
+    }
     pub fn insert<'a, S>(&self, symbol_name: &'a str, symbol_impl: S) -> Result<(), ()>
     where
         S: Symbol + 'static,
     {
-        match self.map.try_borrow_mut() {
+        match self.map.write() {
             Ok(ref mut m) => {
-                m.insert(hash_str(symbol_name), Box::new(symbol_impl));
+                m.insert(hash_str(symbol_name), Arc::new(symbol_impl));
                 Ok(())
             }
             Err(_) => Err(()),
@@ -48,25 +97,53 @@ impl ISymbolMap {
     }
 }
 
+// Special forms are defined with `tail` in place of is_macro, by the evaluation up to their
+// tail position. They are macros.
+macro_rules! defsymbol {
+    ($name: ident, tail, $eval_tail: expr) => {
+        impl Symbol for $name {
+            fn eval(&self, exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+                self.eval_tail(exprs, env)?.eval(env)
+            }
+            fn is_macro(&self) -> bool {
+                return true;
+            }
+            fn eval_tail(
+                &self,
+                exprs: Vec<SExpr>,
+                env: &Envorinment,
+            ) -> Result<TailForm, DovahkiinError> {
+                $eval_tail(exprs, env)
+            }
+        }
+    };
+    ($name: ident, $is_macro: expr, $eval: expr) => {
+        impl Symbol for $name {
+            fn eval(&self, exprs: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError>
+            where
+                Self: Sized,
+            {
+                $eval(exprs, env)
+            }
+            fn is_macro(&self) -> bool {
+                return $is_macro;
+            }
+        }
+    };
+}
+
 macro_rules! defsymbols {
-    ($($sym: expr => $name: ident, $is_macro: expr, $eval: expr);*) => {
+    ($($sym: expr => $name: ident, $kind: tt, $eval: expr);*) => {
         $(
             #[derive(Debug)]
             pub struct $name;
-            impl Symbol for $name {
-                fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, String> where Self: Sized {
-                    $eval(exprs)
-                }
-                fn is_macro(&self) -> bool {
-                    return $is_macro;
-                }
-            }
+            defsymbol!($name, $kind, $eval);
         )*
         lazy_static! {
             pub static ref ISYMBOL_MAP: ISymbolMap = {
-                let mut symbol_map: HashMap<u64, Box<dyn Symbol>> = HashMap::new();
+                let mut symbol_map: HashMap<u64, Arc<dyn Symbol>> = HashMap::new();
                 $(
-                    symbol_map.insert(hash_str($sym), Box::new($name));
+                    symbol_map.insert(hash_str($sym), Arc::new($name));
                 )*
                 ISymbolMap::new(symbol_map)
             };
@@ -74,6 +151,15 @@ macro_rules! defsymbols {
     };
 }
 
+lazy_static! {
+    // number literals without unit in parameters of these symbols are typed by the other operands
+    pub static ref LITERAL_TYPED_SYMBOLS: HashSet<u64> =
+        ["+", "-", "*", "/", "=", "!=", ">", ">=", "<", "<="]
+            .iter()
+            .map(|name| hash_str(name))
+            .collect();
+}
+
 pub fn new_symbol<'a, S>(symbol_id: &'a str, symbol_impl: S) -> Result<(), ()>
 where
     S: Symbol + 'static,
@@ -81,47 +167,47 @@ where
     ISYMBOL_MAP.insert(symbol_id, symbol_impl)
 }
 
-fn check_num_params(num: usize, params: &Vec<SExpr>) -> Result<(), String> {
+fn check_num_params(num: usize, params: &Vec<SExpr>) -> Result<(), DovahkiinError> {
     if num != params.len() {
-        Err(format!(
+        Err(DovahkiinError::arity(format!(
             "Parameter number not match. Except {} but found {}",
             num,
             params.len()
-        ))
+        )))
     } else {
         Ok(())
     }
 }
 
-fn check_params_not_empty(params: &Vec<SExpr>) -> Result<(), String> {
+fn check_params_not_empty(params: &Vec<SExpr>) -> Result<(), DovahkiinError> {
     if params.len() == 0 {
-        Err(format!(
+        Err(DovahkiinError::arity(format!(
             "Parameter number not match. Expected some but found empty"
-        ))
+        )))
     } else {
         Ok(())
     }
 }
 
-fn check_params_not_least_than(num: usize, params: &Vec<SExpr>) -> Result<(), String> {
+fn check_params_not_least_than(num: usize, params: &Vec<SExpr>) -> Result<(), DovahkiinError> {
     if params.len() < num {
-        Err(format!(
+        Err(DovahkiinError::arity(format!(
             "Parameter number not match, Expected at least {} but found {}",
             num,
             params.len()
-        ))
+        )))
     } else {
         Ok(())
     }
 }
 
-fn check_params_not_greater_than(num: usize, params: &Vec<SExpr>) -> Result<(), String> {
+fn check_params_not_greater_than(num: usize, params: &Vec<SExpr>) -> Result<(), DovahkiinError> {
     if params.len() > num {
-        Err(format!(
+        Err(DovahkiinError::arity(format!(
             "Parameter number not match, Expected at most {} but found {}",
             num,
             params.len()
-        ))
+        )))
     } else {
         Ok(())
     }
@@ -134,166 +220,232 @@ fn split_pair(mut exprs: Vec<SExpr>) -> (SExpr, SExpr) {
 }
 
 defsymbols! {
-    "if" => If, true, |exprs| {
+    "if" => If, tail, |exprs, env| {
         check_params_not_least_than(2, &exprs)?;
         check_params_not_greater_than(3, &exprs)?;
-        branching::if_(exprs)
+        branching::if_(exprs, env)
     };
-    "if-not" => IfNot, true, |exprs| {
+    "if-not" => IfNot, tail, |exprs, env| {
         check_params_not_least_than(2, &exprs)?;
         check_params_not_greater_than(3, &exprs)?;
-        branching::if_not(exprs)
+        branching::if_not(exprs, env)
     };
-    "when" => When, true, |exprs| {
+    "when" => When, tail, |exprs, env| {
         check_num_params(2, &exprs)?;
-        branching::when(exprs)
+        branching::when(exprs, env)
     };
-    "when-not" => WhenNot, true, |exprs| {
+    "when-not" => WhenNot, tail, |exprs, env| {
         check_num_params(2, &exprs)?;
-        branching::when_not(exprs)
+        branching::when_not(exprs, env)
     };
-    "=" => Equals, false, |exprs| {
+    "=" => Equals, false, |exprs, _| {
         check_params_not_least_than(2, &exprs)?;
         comparators::equals(exprs)
     };
-    "!=" => NotEquals, false, |exprs| {
+    "!=" => NotEquals, false, |exprs, _| {
         check_num_params(2, &exprs)?;
         comparators::not_equals(exprs)
     };
-    ">" => GreaterThan, false, |exprs| {
+    ">" => GreaterThan, false, |exprs, _| {
         check_params_not_least_than(2, &exprs)?;
         comparators::gt(exprs)
     };
-    ">=" => GreaterThanEquals, false, |exprs| {
+    ">=" => GreaterThanEquals, false, |exprs, _| {
         check_params_not_least_than(2, &exprs)?;
         comparators::gte(exprs)
     };
-    "<" => LessThan, false, |exprs| {
+    "<" => LessThan, false, |exprs, _| {
         check_params_not_least_than(2, &exprs)?;
         comparators::lt(exprs)
     };
-    "<=" => LessThanEquals, false, |exprs| {
+    "<=" => LessThanEquals, false, |exprs, _| {
         check_params_not_least_than(2, &exprs)?;
         comparators::lte(exprs)
     };
-    "+" => Add, false, |exprs| {
+    "+" => Add, false, |exprs, _| {
         check_params_not_empty(&exprs)?;
         arithmetic::add(exprs)
     };
-    "-" => Subtract, false, |exprs| {
+    "-" => Subtract, false, |exprs, _| {
         check_params_not_empty(&exprs)?;
         arithmetic::subtract(exprs)
     };
-    "*" => Multiply, false, |exprs| {
+    "*" => Multiply, false, |exprs, _| {
         check_params_not_empty(&exprs)?;
         arithmetic::multiply(exprs)
     };
-    "/" => Divide, false, |exprs| {
+    "/" => Divide, false, |exprs, _| {
         check_params_not_empty(&exprs)?;
         arithmetic::divide(exprs)
     };
-    "let" => Let, true, |exprs| {
-        bindings::let_binding(exprs)
+    "let" => Let, tail, |exprs, _| {
+        bindings::let_(exprs)
     };
-    "lambda" => Lambda, true, |exprs| {
+    "lambda" => Lambda, true, |exprs, env| {
         check_params_not_least_than(2, &exprs)?;
-        lambda::lambda_placeholder(exprs)
+        lambda::lambda_placeholder(exprs, env)
     };
-    "defunc" => DefineFunc, true, |exprs| {
+    "defunc" => DefineFunc, true, |exprs, env| {
         check_params_not_least_than(3, &exprs)?;
-        functions::defn(exprs)
+        functions::defn(exprs, env)
     };
-    "def" => Define, true, |exprs| {
+    "def" => Define, true, |exprs, env| {
         check_num_params(2, &exprs)?;
-        bindings::define(exprs)
+        bindings::define(exprs, env)
     };
-    "map" => Map, false, |exprs| {
+    "map" => Map, false, |exprs, env| {
         check_num_params(2, &exprs)?;
         let (func, data) = split_pair(exprs);
-        stream::map(func, data)
+        stream::map(func, data, env)
     };
-    "filter" => Filter, false, |exprs| {
+    "apply" => Apply, false, |exprs, env| {
+        check_params_not_least_than(2, &exprs)?;
+        functions::apply(exprs, env)
+    };
+    "filter" => Filter, false, |exprs, env| {
         check_num_params(2, &exprs)?;
         let (func, data) = split_pair(exprs);
-        stream::filter(func, data)
+        stream::filter(func, data, env)
     };
-    "do" => Do, false, |exprs| {
-        misc::do_(exprs)
+    "sort-by" => SortBy, false, |mut exprs, env| {
+        check_params_not_least_than(2, &exprs)?;
+        check_params_not_greater_than(3, &exprs)?;
+        // (sort-by func data) or (sort-by func data :desc)
+        let descending = match exprs.len() {
+            3 => match exprs.pop().unwrap() {
+                SExpr::Keyword(_, ref order) if order == "asc" => false,
+                SExpr::Keyword(_, ref order) if order == "desc" => true,
+                order => return Err(DovahkiinError::type_error(format!(
+                    "Sort order should be :asc or :desc, found {:?}", order
+                ))),
+            },
+            _ => false,
+        };
+        let (func, data) = split_pair(exprs);
+        stream::sort_by(func, data, descending, env)
+    };
+    "take" => Take, false, |exprs, env| {
+        check_num_params(2, &exprs)?;
+        let (num, data) = split_pair(exprs);
+        stream::take(num, data, env)
     };
-    "to_vec" => ToVec, false, |mut exprs| {
+    "do" => Do, tail, |exprs, _| {
+        Ok(TailForm::Body(exprs))
+    };
+    "to_vec" => ToVec, false, |mut exprs, env| {
         check_num_params(1, &exprs)?;
-        stream::to_vec(exprs.pop().unwrap())
+        stream::to_vec(exprs.pop().unwrap(), env)
     };
-    "to_array" => ToArray, false, |mut exprs| {
+    "to_array" => ToArray, false, |mut exprs, env| {
         check_num_params(1, &exprs)?;
-        stream::to_array(exprs.pop().unwrap())
+        stream::to_array(exprs.pop().unwrap(), env)
     };
-    "inc" => Inc, false, |mut exprs| {
+    "inc" => Inc, false, |mut exprs, _| {
         check_num_params(1, &exprs)?;
         arithmetic::inc(exprs.pop().unwrap())
     };
-    "concat" => Concat, false, |exprs| {
-        collections::concat(exprs)
+    "concat" => Concat, false, |exprs, env| {
+        collections::concat(exprs, env)
     };
-    "size" => Size, false, |exprs| {
+    "size" => Size, false, |exprs, _| {
         collections::size(exprs)
     };
-    "hash-map" => GenHashMap, false, |exprs| {
-        collections::hashmap(exprs)
+    "hash-map" => GenHashMap, false, |exprs, env| {
+        collections::hashmap(exprs, env)
+    };
+    "merge" => MergeHashMap, false, |exprs, env| {
+        collections::merge(exprs, env)
     };
-    "merge" => MergeHashMap, false, |exprs| {
-        collections::merge(exprs)
+    "conj" => Conjuction, false, |exprs, env| {
+        collections::conj(exprs, env)
     };
-    "conj" => Conjuction, false, |exprs| {
-        collections::conj(exprs)
+    "or" => Or, true, |exprs, env| {
+        logic::or(exprs, env)
     };
-    "or" => Or, true, |exprs| {
-        logic::or(exprs)
+    "and" => And, true, |exprs, env| {
+        logic::and(exprs, env)
     };
-    "and" => And, true, |exprs| {
-        logic::and(exprs)
+    "not" => Not, false, |mut exprs, _| {
+        check_num_params(1, &exprs)?;
+        logic::not(exprs.pop().unwrap())
     };
-    "cond" => Conditional, true, |exprs| {
-        logic::cond(exprs)
+    "cond" => Conditional, tail, |exprs, env| {
+        logic::cond(exprs, env)
     };
-    "u8" => U8, false, |exprs| {
+    "u8" => U8, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::u8(exprs.get(0).cloned().unwrap())
     };
-    "u16" => U16, false, |exprs| {
+    "u16" => U16, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::u16(exprs.get(0).cloned().unwrap())
     };
-    "u32" => U32, false, |exprs| {
+    "u32" => U32, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::u32(exprs.get(0).cloned().unwrap())
     };
-    "u64" => U64, false, |exprs| {
+    "u64" => U64, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::u64(exprs.get(0).cloned().unwrap())
     };
-    "i8" => I8, false, |exprs| {
+    "i8" => I8, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::i8(exprs.get(0).cloned().unwrap())
     };
-    "i16" => I16, false, |exprs| {
+    "i16" => I16, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::i16(exprs.get(0).cloned().unwrap())
     };
-    "i32" => I32, false, |exprs| {
+    "i32" => I32, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::i32(exprs.get(0).cloned().unwrap())
     };
-    "i64" => I64, false, |exprs| {
+    "i64" => I64, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::i64(exprs.get(0).cloned().unwrap())
     };
-    "f32" => F32, false, |exprs| {
+    "f32" => F32, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::f32(exprs.get(0).cloned().unwrap())
     };
-    "f64" => F64, false, |exprs| {
+    "f64" => F64, false, |exprs, _| {
         check_num_params(1, &exprs)?;
         num_types::f64(exprs.get(0).cloned().unwrap())
+    };
+    "quote" => Quote, true, |mut exprs, _| {
+        check_num_params(1, &exprs)?;
+        quoting::quote(exprs.pop().unwrap())
+    };
+    "quasiquote" => QuasiQuote, true, |mut exprs, env| {
+        check_num_params(1, &exprs)?;
+        quoting::quasiquote(exprs.pop().unwrap(), env)
+    };
+    "unquote" => Unquote, true, |_, _| {
+        Err(DovahkiinError::runtime("unquote can only be used inside quasiquote".to_string()))
+    };
+    "unquote-splicing" => UnquoteSplicing, true, |_, _| {
+        Err(DovahkiinError::runtime("unquote-splicing can only be used inside quasiquote".to_string()))
+    };
+    "try" => Try, true, |exprs, env| {
+        exceptions::try_(exprs, env)
+    };
+    "catch" => Catch, true, |_, _| {
+        Err(DovahkiinError::runtime("catch can only be used inside try".to_string()))
+    };
+    "finally" => Finally, true, |_, _| {
+        Err(DovahkiinError::runtime("finally can only be used inside try".to_string()))
+    };
+    "throw" => Throw, false, |mut exprs, _| {
+        check_num_params(1, &exprs)?;
+        exceptions::throw(exprs.pop().unwrap())
+    };
+    "ex-info" => ExInfo, false, |exprs, env| {
+        check_num_params(2, &exprs)?;
+        let (message, data) = split_pair(exprs);
+        exceptions::ex_info(message, data, env)
+    };
+    "eval" => Eval, false, |mut exprs, env| {
+        check_num_params(1, &exprs)?;
+        quoting::eval(exprs.pop().unwrap(), env)
     }
 }
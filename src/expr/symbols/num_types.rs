@@ -1,6 +1,8 @@
 use super::*;
+use std::convert::TryFrom;
+use std::mem;
 
-pub fn u64(value: SExpr) -> Result<SExpr, String> {
+pub fn u64(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::U64(num as u64))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::U64(num as u64))),
@@ -12,11 +14,13 @@ pub fn u64(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::U64(num as u64))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::U64(num as u64))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::U64(num as u64))),
-        _ => Err("The value cannot be convert into u64".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into u64".to_string(),
+        )),
     }
 }
 
-pub fn u32(value: SExpr) -> Result<SExpr, String> {
+pub fn u32(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::U32(num as u32))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::U32(num as u32))),
@@ -28,11 +32,13 @@ pub fn u32(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::U32(num as u32))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::U32(num as u32))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::U32(num as u32))),
-        _ => Err("The value cannot be convert into u32".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into u32".to_string(),
+        )),
     }
 }
 
-pub fn u16(value: SExpr) -> Result<SExpr, String> {
+pub fn u16(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::U16(num as u16))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::U16(num as u16))),
@@ -44,11 +50,13 @@ pub fn u16(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::U16(num as u16))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::U16(num as u16))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::U16(num as u16))),
-        _ => Err("The value cannot be convert into u16".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into u16".to_string(),
+        )),
     }
 }
 
-pub fn u8(value: SExpr) -> Result<SExpr, String> {
+pub fn u8(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::U8(num as u8))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::U8(num as u8))),
@@ -60,11 +68,13 @@ pub fn u8(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::U8(num as u8))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::U8(num as u8))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::U8(num as u8))),
-        _ => Err("The value cannot be convert into u8".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into u8".to_string(),
+        )),
     }
 }
 
-pub fn i64(value: SExpr) -> Result<SExpr, String> {
+pub fn i64(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::I64(num as i64))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::I64(num as i64))),
@@ -76,11 +86,13 @@ pub fn i64(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::I64(num as i64))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::I64(num as i64))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::I64(num as i64))),
-        _ => Err("The value cannot be convert into i64".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into i64".to_string(),
+        )),
     }
 }
 
-pub fn i32(value: SExpr) -> Result<SExpr, String> {
+pub fn i32(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::I32(num as i32))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::I32(num as i32))),
@@ -92,11 +104,13 @@ pub fn i32(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::I32(num as i32))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::I32(num as i32))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::I32(num as i32))),
-        _ => Err("The value cannot be convert into i32".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into i32".to_string(),
+        )),
     }
 }
 
-pub fn i16(value: SExpr) -> Result<SExpr, String> {
+pub fn i16(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::I16(num as i16))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::I16(num as i16))),
@@ -108,11 +122,13 @@ pub fn i16(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::I16(num as i16))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::I16(num as i16))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::I16(num as i16))),
-        _ => Err("The value cannot be convert into i16".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into i16".to_string(),
+        )),
     }
 }
 
-pub fn i8(value: SExpr) -> Result<SExpr, String> {
+pub fn i8(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::I8(num as i8))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::I8(num as i8))),
@@ -124,11 +140,13 @@ pub fn i8(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::I8(num as i8))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::I8(num as i8))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::I8(num as i8))),
-        _ => Err("The value cannot be convert into i8".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into i8".to_string(),
+        )),
     }
 }
 
-pub fn f32(value: SExpr) -> Result<SExpr, String> {
+pub fn f32(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::F32(num as f32))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::F32(num as f32))),
@@ -140,11 +158,13 @@ pub fn f32(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::F32(num as f32))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::F32(num as f32))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::F32(num as f32))),
-        _ => Err("The value cannot be convert into f32".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into f32".to_string(),
+        )),
     }
 }
 
-pub fn f64(value: SExpr) -> Result<SExpr, String> {
+pub fn f64(value: SExpr) -> Result<SExpr, DovahkiinError> {
     match value {
         SExpr::Value(Value::U8(num)) => Ok(SExpr::Value(Value::F64(num as f64))),
         SExpr::Value(Value::U16(num)) => Ok(SExpr::Value(Value::F64(num as f64))),
@@ -156,6 +176,109 @@ pub fn f64(value: SExpr) -> Result<SExpr, String> {
         SExpr::Value(Value::I64(num)) => Ok(SExpr::Value(Value::F64(num as f64))),
         SExpr::Value(Value::F32(num)) => Ok(SExpr::Value(Value::F64(num as f64))),
         SExpr::Value(Value::F64(num)) => Ok(SExpr::Value(Value::F64(num as f64))),
-        _ => Err("The value cannot be convert into f64".to_string()),
+        _ => Err(DovahkiinError::type_error(
+            "The value cannot be convert into f64".to_string(),
+        )),
     }
 }
+
+fn int_of(value: &Value) -> Option<i128> {
+    match value {
+        &Value::U8(num) => Some(num as i128),
+        &Value::U16(num) => Some(num as i128),
+        &Value::U32(num) => Some(num as i128),
+        &Value::U64(num) => Some(num as i128),
+        &Value::I8(num) => Some(num as i128),
+        &Value::I16(num) => Some(num as i128),
+        &Value::I32(num) => Some(num as i128),
+        &Value::I64(num) => Some(num as i128),
+        _ => None,
+    }
+}
+
+fn float_of(value: &Value) -> Option<f64> {
+    match value {
+        &Value::F32(num) => Some(num as f64),
+        &Value::F64(num) => Some(num),
+        _ => None,
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    int_of(value).is_some() || float_of(value).is_some()
+}
+
+macro_rules! checked_int {
+    ($type: ident, $t: ty, $num: expr) => {
+        <$t>::try_from($num).map(Value::$type).map_err(|_| {
+            DovahkiinError::type_error(format!(
+                "Number literal {} is out of range for {}",
+                $num,
+                stringify!($t)
+            ))
+        })
+    };
+}
+
+fn literal_as(literal: Value, target: &Value) -> Result<Value, DovahkiinError> {
+    if let Some(num) = int_of(&literal) {
+        match target {
+            &Value::U8(_) => checked_int!(U8, u8, num),
+            &Value::U16(_) => checked_int!(U16, u16, num),
+            &Value::U32(_) => checked_int!(U32, u32, num),
+            &Value::U64(_) => checked_int!(U64, u64, num),
+            &Value::I8(_) => checked_int!(I8, i8, num),
+            &Value::I16(_) => checked_int!(I16, i16, num),
+            &Value::I32(_) => checked_int!(I32, i32, num),
+            &Value::I64(_) => checked_int!(I64, i64, num),
+            &Value::F32(_) => Ok(Value::F32(num as f32)),
+            &Value::F64(_) => Ok(Value::F64(num as f64)),
+            _ => Ok(literal),
+        }
+    } else if let Some(num) = float_of(&literal) {
+        match target {
+            &Value::F32(_) => Ok(Value::F32(num as f32)),
+            &Value::F64(_) => Ok(Value::F64(num)),
+            _ if int_of(target).is_some() => Err(DovahkiinError::type_error(format!(
+                "Float literal {} cannot be used as {:?}",
+                num, target
+            ))),
+            _ => Ok(literal),
+        }
+    } else {
+        Ok(literal)
+    }
+}
+
+// Number literals without unit take the type of the first typed number in the parameters,
+// so (+ x 1) works for x of any number type. With only literals, int literals follow float ones.
+pub fn type_literals(params: &mut Vec<SExpr>, literals: &[usize]) -> Result<(), DovahkiinError> {
+    let numbers = || {
+        params
+            .iter()
+            .enumerate()
+            .filter_map(|(i, param)| match param {
+                &SExpr::Value(ref value) if is_number(value) => Some((i, value.clone())),
+                _ => None,
+            })
+    };
+    let target = numbers()
+        .filter(|&(i, _)| !literals.contains(&i))
+        .map(|(_, value)| value)
+        .next()
+        .or_else(|| {
+            numbers()
+                .map(|(_, value)| value)
+                .find(|value| float_of(value).is_some())
+        });
+    if let Some(target) = target {
+        for &i in literals {
+            let param = mem::replace(&mut params[i], SExpr::Value(Value::Null));
+            params[i] = match param {
+                SExpr::Value(literal) => SExpr::Value(literal_as(literal, &target)?),
+                other => other,
+            };
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use super::*;
+
+// Argument of a (name arg) form, used to find unquote forms in quasiquoted code
+fn form_arg<'a>(expr: &'a SExpr, name: &str) -> Option<&'a SExpr> {
+    if let &SExpr::List(ref items) = expr.unlocated() {
+        if let (Some(&SExpr::ISymbol(_, ref head)), 2) = (items.first(), items.len()) {
+            if head == name {
+                return items.get(1);
+            }
+        }
+    }
+    None
+}
+
+pub fn quote(expr: SExpr) -> Result<SExpr, DovahkiinError> {
+    Ok(expr.strip_locations())
+}
+
+pub fn quasiquote(expr: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    if let Some(unquoted) = form_arg(&expr, "unquote") {
+        return unquoted.clone().eval(env);
+    }
+    if form_arg(&expr, "unquote-splicing").is_some() {
+        return Err(DovahkiinError::type_error(format!(
+            "unquote-splicing can only be used inside a list or vector, found {:?}",
+            expr
+        )));
+    }
+    match expr {
+        SExpr::Located(_, expr) => quasiquote(*expr, env),
+        SExpr::List(items) => Ok(SExpr::List(quasiquote_items(items, env)?)),
+        SExpr::Vec(items) => Ok(SExpr::Vec(quasiquote_items(items, env)?)),
+        _ => quote(expr),
+    }
+}
+
+fn quasiquote_items(items: Vec<SExpr>, env: &Envorinment) -> Result<Vec<SExpr>, DovahkiinError> {
+    let mut result = Vec::with_capacity(items.len());
+    for item in items {
+        let spliced = match form_arg(&item, "unquote-splicing") {
+            Some(spliced) => spliced.clone().eval(env)?,
+            None => {
+                result.push(quasiquote(item, env)?);
+                continue;
+            }
+        };
+        match spliced {
+            SExpr::List(mut exprs) | SExpr::Vec(mut exprs) => result.append(&mut exprs),
+            SExpr::Value(Value::Array(array)) => {
+                result.extend(array.into_iter().map(SExpr::Value));
+            }
+            SExpr::Value(Value::Null) => {}
+            _ => {
+                return Err(DovahkiinError::type_error(format!(
+                    "unquote-splicing need a list, vector or array, found {:?}",
+                    spliced
+                )));
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub fn eval(expr: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    expr.eval(env)
+}
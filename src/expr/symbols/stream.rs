@@ -1,74 +1,192 @@
-use super::functions::eval_function;
-use super::utils::is_true;
+use super::functions::apply_function;
+use super::utils::{compare_values, is_true};
 use super::*;
+use expr::interpreter::{array_size, vec_size};
+use std::cmp::Ordering;
+use std::mem;
 
-pub fn to_array(expr: SExpr) -> Result<SExpr, String> {
+pub fn to_array(expr: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     match expr {
         SExpr::Vec(vec) => {
-            let mut array = Vec::new();
+            env.allocate(array_size(vec.len()))?;
+            let mut array = Vec::with_capacity(vec.len());
             for expr in vec {
-                if let SExpr::Value(val) = expr {
+                if let SExpr::Value(val) | SExpr::Untyped(val) = expr {
                     array.push(val)
                 } else {
-                    return Err(format!("Data {:?} cannot be value", expr));
+                    return Err(DovahkiinError::type_error(format!(
+                        "Data {:?} cannot be value",
+                        expr
+                    )));
                 }
             }
             return Ok(SExpr::Value(Value::Array(array)));
         }
         SExpr::Value(Value::Array(_)) => Ok(expr),
         _ => {
-            return Err(format!(
+            return Err(DovahkiinError::type_error(format!(
                 "Only Vector can convert into array, found {:?}",
                 expr
-            ))
+            )))
         }
     }
 }
 
-pub fn to_vec(expr: SExpr) -> Result<SExpr, String> {
+pub fn to_vec(expr: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     match expr {
         SExpr::Value(Value::Array(array)) => {
+            env.allocate(vec_size(array.len()))?;
             return Ok(SExpr::Vec(
                 array.into_iter().map(|val| SExpr::Value(val)).collect(),
             ));
         }
         SExpr::Vec(_) => Ok(expr),
         _ => {
-            return Err(format!(
+            return Err(DovahkiinError::type_error(format!(
                 "Only array value can convert into vector, found {:?}",
                 expr
-            ))
+            )))
         }
     }
 }
 
-pub fn map(func: SExpr, data: SExpr) -> Result<SExpr, String> {
+pub fn map(func: SExpr, data: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     match data {
-        SExpr::Value(Value::Array(_)) => return map(func, to_vec(data)?),
+        SExpr::Value(Value::Array(_)) => return map(func, to_vec(data, env)?, env),
         SExpr::Vec(expr_list) => {
+            env.allocate(vec_size(expr_list.len()))?;
             let mut result = Vec::with_capacity(expr_list.len());
             for expr in expr_list {
-                result.push(eval_function(&func, vec![expr.eval()?])?)
+                result.push(apply_function(&func, vec![expr.eval(env)?], env)?)
             }
             return Ok(SExpr::Vec(result));
         }
-        _ => return Err(format!("Cannot map function on {:?}", data)),
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Cannot map function on {:?}",
+                data
+            )))
+        }
     }
 }
 
-pub fn filter(func: SExpr, data: SExpr) -> Result<SExpr, String> {
+pub fn filter(func: SExpr, data: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
     match data {
-        SExpr::Value(Value::Array(_)) => return filter(func, to_vec(data)?),
+        SExpr::Value(Value::Array(_)) => return filter(func, to_vec(data, env)?, env),
         SExpr::Vec(expr_list) => {
+            env.allocate(vec_size(expr_list.len()))?;
             let mut result = Vec::with_capacity(expr_list.len());
             for expr in expr_list {
-                let val = expr.eval()?;
-                if is_true(eval_function(&func, vec![val.clone()])?) {
+                let val = expr.eval(env)?;
+                if is_true(apply_function(&func, vec![val.clone()], env)?) {
                     result.push(val)
                 }
             }
             return Ok(SExpr::Vec(result));
         }
-        _ => return Err(format!("Cannot map function on {:?}", data)),
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Cannot map function on {:?}",
+                data
+            )))
+        }
+    }
+}
+
+// Stable sort by the value of the function on each item
+pub fn sort_by(
+    func: SExpr,
+    data: SExpr,
+    descending: bool,
+    env: &Envorinment,
+) -> Result<SExpr, DovahkiinError> {
+    match data {
+        SExpr::Value(Value::Array(_)) => return sort_by(func, to_vec(data, env)?, descending, env),
+        SExpr::Vec(expr_list) => {
+            // the keyed items and the sorted vector
+            env.allocate(
+                expr_list.len() * mem::size_of::<(Value, SExpr)>() + vec_size(expr_list.len()),
+            )?;
+            let mut keyed = Vec::with_capacity(expr_list.len());
+            for expr in expr_list {
+                let val = expr.eval(env)?;
+                match apply_function(&func, vec![val.clone()], env)? {
+                    SExpr::Value(key) => keyed.push((key, val)),
+                    key => {
+                        return Err(DovahkiinError::type_error(format!(
+                            "Sort key should be a value, found {:?}",
+                            key
+                        )))
+                    }
+                }
+            }
+            let mut error = None;
+            keyed.sort_by(|&(ref a, _), &(ref b, _)| {
+                let ordering = compare_values(a, b).unwrap_or_else(|e| {
+                    error = Some(e);
+                    Ordering::Equal
+                });
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+            if let Some(e) = error {
+                return Err(e);
+            }
+            return Ok(SExpr::Vec(keyed.into_iter().map(|(_, val)| val).collect()));
+        }
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Cannot sort {:?}",
+                data
+            )))
+        }
+    }
+}
+
+pub fn take(num: SExpr, data: SExpr, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+    let num = match num {
+        SExpr::Value(Value::U8(n)) => n as i64,
+        SExpr::Value(Value::U16(n)) => n as i64,
+        SExpr::Value(Value::U32(n)) => n as i64,
+        SExpr::Value(Value::U64(n)) => n.min(i64::max_value() as u64) as i64,
+        SExpr::Value(Value::I8(n)) => n as i64,
+        SExpr::Value(Value::I16(n)) => n as i64,
+        SExpr::Value(Value::I32(n)) => n as i64,
+        SExpr::Value(Value::I64(n)) => n,
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Number of items to take should be an integer, found {:?}",
+                num
+            )))
+        }
+    };
+    if num < 0 {
+        return Err(DovahkiinError::runtime(format!(
+            "Cannot take {} items",
+            num
+        )));
+    }
+    match data {
+        SExpr::Value(Value::Array(_)) => {
+            return take(SExpr::Value(Value::I64(num)), to_vec(data, env)?, env)
+        }
+        SExpr::Vec(expr_list) => {
+            let len = expr_list.len().min(num as usize);
+            env.allocate(vec_size(len))?;
+            let mut result = Vec::with_capacity(len);
+            for expr in expr_list.into_iter().take(len) {
+                result.push(expr.eval(env)?);
+            }
+            return Ok(SExpr::Vec(result));
+        }
+        _ => {
+            return Err(DovahkiinError::type_error(format!(
+                "Cannot take items from {:?}",
+                data
+            )))
+        }
     }
 }
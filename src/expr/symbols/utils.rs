@@ -1,4 +1,5 @@
 use super::*;
+use std::cmp::Ordering;
 
 pub fn is_true(expr: SExpr) -> bool {
     match expr {
@@ -6,3 +7,28 @@ pub fn is_true(expr: SExpr) -> bool {
         _ => true, // anything else than false and null value will be considered as yes
     }
 }
+
+macro_rules! compare_same_types {
+    ($a: expr, $b: expr, $($type: ident),*) => {
+        match ($a, $b) {
+            $(
+                (&Value::$type(ref a), &Value::$type(ref b)) => a.partial_cmp(b),
+            )*
+            _ => None,
+        }
+    };
+}
+
+// Ordering of values of the same type, nil is ordered before any value
+pub fn compare_values(a: &Value, b: &Value) -> Result<Ordering, DovahkiinError> {
+    let ordering = match (a, b) {
+        (&Value::Null, &Value::Null) => Some(Ordering::Equal),
+        (&Value::Null, _) => Some(Ordering::Less),
+        (_, &Value::Null) => Some(Ordering::Greater),
+        _ => compare_same_types!(
+            a, b, Bool, Char, I8, I16, I32, I64, U8, U16, U32, U64, F32, F64, String, Id
+        ),
+    };
+    ordering
+        .ok_or_else(|| DovahkiinError::type_error(format!("Cannot compare {:?} with {:?}", a, b)))
+}
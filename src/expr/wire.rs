@@ -0,0 +1,54 @@
+use bifrost::utils::serde::{deserialize, serialize};
+use bifrost_hasher::hash_bytes;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use error::DovahkiinError;
+use expr::SExpr;
+use std::io::Cursor;
+
+// Binary encoding of expressions to ship parsed queries between servers.
+// Layout: magic, format version (u16), checksum of the body (u64), body.
+// The body is the `bifrost::utils::serde` encoding of the expression.
+
+pub const MAGIC: [u8; 2] = *b"DV";
+/// Bump when `SExpr`, `Span` or the value types change their encoding
+pub const VERSION: u16 = 2;
+const HEADER_SIZE: usize = 12;
+
+/// Encodes the expression with its source locations, call `strip_locations` first
+/// to ship a smaller query.
+pub fn encode(expr: &SExpr) -> Vec<u8> {
+    let body = serialize(expr);
+    let mut data = Vec::with_capacity(HEADER_SIZE + body.len());
+    data.extend_from_slice(&MAGIC);
+    data.write_u16::<BigEndian>(VERSION).unwrap();
+    data.write_u64::<BigEndian>(hash_bytes(&body)).unwrap();
+    data.extend_from_slice(&body);
+    data
+}
+
+/// Decodes an expression from `encode`. Data from another format version, or damaged
+/// on the way, is rejected with a decode error.
+pub fn decode(data: &[u8]) -> Result<SExpr, DovahkiinError> {
+    if data.len() < HEADER_SIZE || data[..2] != MAGIC {
+        return Err(DovahkiinError::decode(
+            "Not an encoded expression".to_string(),
+        ));
+    }
+    let mut header = Cursor::new(&data[2..HEADER_SIZE]);
+    let version = header.read_u16::<BigEndian>().unwrap();
+    let checksum = header.read_u64::<BigEndian>().unwrap();
+    if version != VERSION {
+        return Err(DovahkiinError::decode(format!(
+            "Encoded expression has format version {}, expect {}",
+            version, VERSION
+        )));
+    }
+    let body = &data[HEADER_SIZE..];
+    if hash_bytes(body) != checksum {
+        return Err(DovahkiinError::decode(
+            "Encoded expression does not match its checksum".to_string(),
+        ));
+    }
+    deserialize(body)
+        .ok_or_else(|| DovahkiinError::decode("Malformed encoded expression".to_string()))
+}
@@ -0,0 +1,25 @@
+use error::DovahkiinError;
+use expr::interpreter::Interpreter;
+use expr::SExpr;
+use parser::json as json_parser;
+use parser::lisp::ParserOptions;
+use printer::json as json_printer;
+
+pub fn parse_to_expr(code: &str) -> Result<SExpr, DovahkiinError> {
+    json_parser::parse_to_sexpr(code)
+}
+
+pub fn parse_to_expr_with_options(
+    code: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    json_parser::parse_to_sexpr_with_options(code, options)
+}
+
+pub fn eval_string(interpreter: &Interpreter, code: &str) -> Result<SExpr, DovahkiinError> {
+    interpreter.eval(vec![parse_to_expr(code)?])
+}
+
+pub fn to_json(expr: &SExpr) -> Result<String, DovahkiinError> {
+    json_printer::print_expr(expr)
+}
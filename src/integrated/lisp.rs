@@ -1,21 +1,51 @@
+use error::DovahkiinError;
 use expr::interpreter::Interpreter;
 use expr::SExpr;
 use lexer::lisp as lisp_lexer;
+use lexer::lisp::TokenReader;
 use parser::lisp as lisp_parser;
+use parser::lisp::{FormReader, ParserOptions};
+use std::io::BufRead;
+use types::OwnedValue as Value;
 
-pub fn parse_to_expr<'a>(code: &'a str) -> Result<Vec<SExpr>, String> {
+pub fn parse_to_expr<'a>(code: &'a str) -> Result<Vec<SExpr>, DovahkiinError> {
     let tokens = lisp_lexer::tokenize_str(code)?;
     lisp_parser::parse_to_sexpr(tokens)
 }
 
+pub fn parse_to_expr_with_options<'a>(
+    code: &'a str,
+    options: &ParserOptions,
+) -> Result<Vec<SExpr>, DovahkiinError> {
+    let tokens = lisp_lexer::tokenize_str(code)?;
+    lisp_parser::parse_to_sexpr_with_options(tokens, options)
+}
+
+/// Forms are read lazily, one at a time, from the reader
+pub fn read_forms<'a, R: BufRead + 'a>(reader: R) -> FormReader<TokenReader<'a>> {
+    FormReader::new(TokenReader::new(reader), ParserOptions::default())
+}
+
 pub fn get_interpreter() -> Interpreter {
     Interpreter::new()
 }
 
-pub fn eval(interpreter: &Interpreter, exprs: Vec<SExpr>) -> Result<SExpr, String> {
+pub fn eval(interpreter: &Interpreter, exprs: Vec<SExpr>) -> Result<SExpr, DovahkiinError> {
     interpreter.eval(exprs)
 }
 
-pub fn eval_string<'a>(interpreter: &Interpreter, code: &'a str) -> Result<SExpr, String> {
+pub fn eval_string<'a>(interpreter: &Interpreter, code: &'a str) -> Result<SExpr, DovahkiinError> {
     eval(interpreter, parse_to_expr(code)?)
 }
+
+/// Evaluates forms as they are read, returns the result of the last one
+pub fn eval_reader<'a, R: BufRead + 'a>(
+    interpreter: &Interpreter,
+    reader: R,
+) -> Result<SExpr, DovahkiinError> {
+    let mut result = SExpr::Value(Value::Null);
+    for form in read_forms(reader) {
+        result = interpreter.eval(vec![form?])?;
+    }
+    Ok(result)
+}
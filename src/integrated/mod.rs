@@ -1 +1,3 @@
+pub mod json;
 pub mod lisp;
+pub mod sql;
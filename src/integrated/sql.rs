@@ -0,0 +1,21 @@
+use error::DovahkiinError;
+use expr::interpreter::Interpreter;
+use expr::SExpr;
+use parser::lisp::ParserOptions;
+use parser::sql as sql_parser;
+
+pub fn parse_to_expr(code: &str) -> Result<SExpr, DovahkiinError> {
+    sql_parser::parse_to_sexpr(code)
+}
+
+pub fn parse_to_expr_with_options(
+    code: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    sql_parser::parse_to_sexpr_with_options(code, options)
+}
+
+/// Runs the query on the interpreter, the source is looked up in its bindings
+pub fn eval_string(interpreter: &Interpreter, code: &str) -> Result<SExpr, DovahkiinError> {
+    interpreter.eval(vec![parse_to_expr(code)?])
+}
@@ -1,16 +1,31 @@
+use error::{DovahkiinError, ErrorKind};
+use span::{Position, Span};
 use std::collections::HashSet;
+use std::io::{self, BufRead};
 
 #[derive(Debug)]
 pub enum Token {
     LeftParentheses,
     RightParentheses,
     Symbol(String),
+    Keyword(String),
     IntNumber(String, String),
     FloatNumber(String, String),
     String(String),
+    Bytes(Vec<u8>),
+    Char(char),
     LeftVecParentheses,
     RightVecParentheses,
-    // Quote
+    LeftMapParentheses,
+    RightMapParentheses,
+    LineComment(String),
+    BlockComment(String),
+    DatumComment,
+    Tag(String),
+    Quote,
+    QuasiQuote,
+    Unquote,
+    UnquoteSplicing,
 }
 
 impl ToString for Token {
@@ -19,40 +34,135 @@ impl ToString for Token {
             &Token::LeftParentheses => String::from("("),
             &Token::RightParentheses => String::from(")"),
             &Token::Symbol(ref s) => s.clone(),
+            &Token::Keyword(ref s) => format!(":{}", s),
             &Token::IntNumber(ref n, ref u) => format!("{}{}", n, u),
             &Token::FloatNumber(ref n, ref u) => format!("{}{}", n, u),
-            &Token::String(ref s) => format!("\"{}\"", s),
+            &Token::String(ref s) => format!("{:?}", s),
+            &Token::Bytes(ref bytes) => {
+                let escaped: Vec<u8> = bytes
+                    .iter()
+                    .flat_map(|&b| ::std::ascii::escape_default(b))
+                    .collect();
+                format!("b\"{}\"", String::from_utf8_lossy(&escaped))
+            }
+            &Token::Char(c) => match CHAR_NAMES.iter().find(|&&(_, named)| named == c) {
+                Some(&(name, _)) => format!("\\{}", name),
+                None if c.is_whitespace() || c.is_control() => {
+                    format!("\\u{{{:X}}}", c as u32)
+                }
+                None => format!("\\{}", c),
+            },
             &Token::LeftVecParentheses => String::from("["),
             &Token::RightVecParentheses => String::from("]"),
+            &Token::LeftMapParentheses => String::from("{"),
+            &Token::RightMapParentheses => String::from("}"),
+            &Token::LineComment(ref s) => format!(";{}", s),
+            &Token::BlockComment(ref s) => format!("#|{}|#", s),
+            &Token::DatumComment => String::from("#_"),
+            &Token::Tag(ref s) => format!("#{}", s),
+            &Token::Quote => String::from("'"),
+            &Token::QuasiQuote => String::from("`"),
+            &Token::Unquote => String::from("~"),
+            &Token::UnquoteSplicing => String::from("~@"),
         }
     }
 }
 
-pub struct CharIter {
+#[derive(Debug)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+pub struct CharIter<'a> {
     chars: Vec<char>,
     current_pos: usize,
+    // chars dropped from the front of `chars` when reading from a reader
+    consumed: usize,
+    line: usize,
+    column: usize,
+    reader: Option<Box<dyn BufRead + 'a>>,
+    read_error: Option<io::Error>,
 }
 
-impl CharIter {
-    pub fn new(data: Vec<char>) -> CharIter {
+impl<'a> CharIter<'a> {
+    pub fn new(data: Vec<char>) -> CharIter<'a> {
         CharIter {
             chars: data,
             current_pos: 0,
+            consumed: 0,
+            line: 1,
+            column: 1,
+            reader: None,
+            read_error: None,
         }
     }
+    /// Chars are read line by line from the reader when the lexer needs them
+    pub fn from_reader<R: BufRead + 'a>(reader: R) -> CharIter<'a> {
+        let mut iter = CharIter::new(Vec::new());
+        iter.reader = Some(Box::new(reader));
+        iter
+    }
+    fn get(&mut self, pos: usize) -> Option<char> {
+        while pos >= self.chars.len() && self.reader.is_some() {
+            let mut line = String::new();
+            match self.reader.as_mut().unwrap().read_line(&mut line) {
+                Ok(0) => self.reader = None,
+                Ok(_) => self.chars.extend(line.chars()),
+                Err(e) => {
+                    self.read_error = Some(e);
+                    self.reader = None;
+                }
+            }
+        }
+        self.chars.get(pos).cloned()
+    }
     pub fn next(&mut self) -> Option<char> {
+        match self.chars.get(self.current_pos) {
+            Some(&'\n') => {
+                self.line += 1;
+                self.column = 1;
+            }
+            Some(_) => self.column += 1,
+            None => {}
+        }
         self.current_pos += 1;
-        self.chars.get(self.current_pos).cloned()
+        let pos = self.current_pos;
+        self.get(pos)
     }
-    pub fn peek_next(&self) -> Option<char> {
-        self.chars.get(self.current_pos + 1).cloned()
+    pub fn peek_next(&mut self) -> Option<char> {
+        let pos = self.current_pos + 1;
+        self.get(pos)
     }
 
     pub fn current(&mut self) -> Option<char> {
-        self.chars.get(self.current_pos).cloned()
+        let pos = self.current_pos;
+        self.get(pos)
+    }
+
+    pub fn position(&self) -> Position {
+        Position::new(self.line, self.column, self.consumed + self.current_pos)
+    }
+
+    // drops chars already read from a reader so memory stays bounded
+    fn compact(&mut self) {
+        if self.reader.is_some() && self.current_pos > 0 {
+            self.chars.drain(..self.current_pos);
+            self.consumed += self.current_pos;
+            self.current_pos = 0;
+        }
     }
 }
 
+// chars that are written by name, like \newline
+static CHAR_NAMES: [(&str, char); 5] = [
+    ("newline", '\n'),
+    ("space", ' '),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+];
+
 lazy_static! {
     static ref INT_NUM_TYPES: HashSet<String> =
         vec!["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"]
@@ -85,214 +195,581 @@ fn readout_whitespaces(iter: &mut CharIter) {
     }
 }
 
-fn read_number(first: char, iter: &mut CharIter) -> Result<Token, String> {
-    let mut digit_chars = vec![first];
-    let mut unit_chars = Vec::new();
-    let mut is_float_number = false;
+fn read_number(first: char, iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let start = iter.position();
+    let mut chars = vec![first];
     while let Some(c) = iter.next() {
+        if is_delimiter(c) {
+            break;
+        }
+        chars.push(c);
+    }
+    split_number(chars, start)
+}
+
+// Splits a number literal like -0xFFu64, 1_000u32 or 6.02e23f64 into its digits and unit.
+// The digits keep their sign, radix prefix and '_' separators, the parser deals with them.
+fn split_number(chars: Vec<char>, start: Position) -> Result<Token, DovahkiinError> {
+    let literal: String = chars.iter().collect();
+    // errors point at the offending char, numbers never span lines
+    let at = |index: usize| {
+        Span::at(Position::new(
+            start.line,
+            start.column + index,
+            start.offset + index,
+        ))
+    };
+    let sign_len = if chars[0] == '-' { 1 } else { 0 };
+    let radix = match (chars.get(sign_len), chars.get(sign_len + 1)) {
+        (Some(&'0'), Some(&'x')) => 16,
+        (Some(&'0'), Some(&'o')) => 8,
+        (Some(&'0'), Some(&'b')) => 2,
+        _ => 10,
+    };
+    let mut pos = if radix == 10 { sign_len } else { sign_len + 2 };
+    let mut has_digit = false;
+    let mut is_float_number = false;
+    while let Some(&c) = chars.get(pos) {
         match c {
-            NUMBER_PATTERN!() => {
-                match (unit_chars.first(), unit_chars.get(1), c) {
-                    // terminal states
-                    (Some(&'u'), None,       '8') | // u8
-                    (Some(&'u'), Some(&'1'), '6') | // u16
-                    (Some(&'u'), Some(&'3'), '2') | // u32
-                    (Some(&'u'), Some(&'6'), '4') | // u64
-                    (Some(&'i'), None,       '8') | // i8
-                    (Some(&'i'), Some(&'1'), '6') | // i16
-                    (Some(&'i'), Some(&'3'), '2') | // i32
-                    (Some(&'i'), Some(&'6'), '4') | // i64
-                    (Some(&'f'), Some(&'3'), '2') | // f32
-                    (Some(&'f'), Some(&'6'), '4')   // f64
-                    => {
-                        unit_chars.push(c);
-                        break;
-                    }
-                    // mid states
-                    (Some(&'u'), None, '1') | // u16
-                    (Some(&'u'), None, '3') | // u32
-                    (Some(&'u'), None, '6') | // u64
-                    (Some(&'i'), None, '1') | // i16
-                    (Some(&'i'), None, '3') | // i32
-                    (Some(&'i'), None, '6') | // i64
-                    (Some(&'f'), None, '3') | // f32
-                    (Some(&'f'), None, '6')   // f64
-                    => {
-                        unit_chars.push(c);
-                    },
-                    (None, _, _) => {
-                        digit_chars.push(c);
-                    }
-                    _ => {
-                        return Err(format!("Unexpected token '{}' for number unit", c))
-                    }
-                }
+            '_' => {}
+            '0'..='9' | 'a'..='f' | 'A'..='F' if c.is_digit(radix) => has_digit = true,
+            '0'..='9' => {
+                return Err(DovahkiinError::lex(format!(
+                    "Invalid digit '{}' for base {} number '{}'",
+                    c, radix, literal
+                ))
+                .with_span(at(pos)));
             }
-            '.' => {
-                if !is_float_number {
-                    is_float_number = true;
-                    digit_chars.push(c);
-                } else {
-                    return Err("There is a floating point in the number already".to_string());
+            '.' if radix == 10 && !is_float_number => is_float_number = true,
+            'e' | 'E' if radix == 10 && has_digit => {
+                // exponent, with optional sign
+                is_float_number = true;
+                pos += 1;
+                if let Some(&'+') | Some(&'-') = chars.get(pos) {
+                    pos += 1;
+                }
+                let exponent_start = pos;
+                while let Some(&('0'..='9')) | Some(&'_') = chars.get(pos) {
+                    pos += 1;
+                }
+                if pos == exponent_start {
+                    return Err(DovahkiinError::lex(format!(
+                        "Missing exponent digits in number '{}'",
+                        literal
+                    ))
+                    .with_span(at(pos)));
                 }
-            }
-            'u' | 'i' | 'f' => {
-                unit_chars.push(c);
-            }
-            ' ' | '\t' | '\r' | '\n' => {
                 break;
             }
-            _ => return Err(format!("Unexpected token '{}' for number", c)),
+            _ => break,
         }
+        pos += 1;
     }
-    let digit_part: String = digit_chars.into_iter().collect();
-    let unit_part: String = unit_chars.into_iter().collect();
-    iter.next();
-    if is_float_number {
-        if !FLOAT_NUM_TYPES.contains(&unit_part) {
-            return Err(format!(
-                "Invalid float number '{}{}'",
-                digit_part, unit_part
-            ));
+    if !has_digit {
+        return Err(
+            DovahkiinError::lex(format!("Number '{}' has no digits", literal)).with_span(at(0)),
+        );
+    }
+    let digit_part: String = chars[..pos].iter().collect();
+    let unit_part: String = chars[pos..].iter().collect();
+    // number without unit will be typed by the parser
+    if unit_part.is_empty() {
+        return Ok(if is_float_number {
+            Token::FloatNumber(digit_part, unit_part)
+        } else {
+            Token::IntNumber(digit_part, unit_part)
+        });
+    }
+    if FLOAT_NUM_TYPES.contains(&unit_part) {
+        if radix != 10 {
+            return Err(DovahkiinError::lex(format!(
+                "Base {} number '{}' cannot be a float",
+                radix, literal
+            ))
+            .with_span(at(pos)));
         }
         return Ok(Token::FloatNumber(digit_part, unit_part));
-    } else {
-        if !INT_NUM_TYPES.contains(&unit_part) {
-            return Err(format!(
-                "Invalid integer number '{}{}'",
-                digit_part, unit_part
-            ));
-        }
+    }
+    if INT_NUM_TYPES.contains(&unit_part) && !is_float_number {
         return Ok(Token::IntNumber(digit_part, unit_part));
     }
+    Err(DovahkiinError::lex(format!(
+        "Invalid {} number '{}'",
+        if is_float_number { "float" } else { "integer" },
+        literal
+    ))
+    .with_span(at(pos)))
 }
 
-fn read_escaped_char(iter: &mut CharIter) -> Result<char, String> {
-    while let Some(c) = iter.next() {
-        match c {
-            'u' | 'U' => {
-                // read 6 digit hex number as unicode
-                let mut hex_chars: Vec<char> = Vec::new();
-                for _ in 0..6 {
-                    if let Some(c) = iter.next() {
-                        let c = c.to_ascii_lowercase();
-                        match c {
-                            NUMBER_PATTERN!() | 'a'..='f' => {
-                                hex_chars.push(c);
-                            }
-                            _ => break,
-                        }
-                    } else {
-                        break;
-                    }
+// Escape sequence after '\\', None for a line continuation which skips the line break
+// and the indentation after it
+fn read_escaped_char(iter: &mut CharIter, is_bytes: bool) -> Result<Option<char>, DovahkiinError> {
+    let c = match iter.next() {
+        Some(c) => c,
+        None => {
+            return Err(DovahkiinError::incomplete(
+                "Unterminated string".to_string(),
+            ))
+        }
+    };
+    let escaped = match c {
+        'u' if is_bytes => {
+            return Err(DovahkiinError::lex(
+                "Unicode escape is not allowed in byte strings".to_string(),
+            ))
+        }
+        'u' => read_unicode_escape(iter)?,
+        'x' => {
+            let hex: String = vec![iter.next(), iter.next()]
+                .into_iter()
+                .filter_map(|c| c)
+                .collect();
+            match u8::from_str_radix(&hex, 16) {
+                Ok(byte) if is_bytes || byte <= 0x7f => byte as char,
+                Ok(_) => {
+                    return Err(DovahkiinError::lex(format!(
+                        "Escape \\x{} is out of ASCII range, use \\u{{...}} instead",
+                        hex
+                    )))
                 }
-                let unicode_hex: String = hex_chars.into_iter().collect();
-                let unicode = u32::from_str_radix(&unicode_hex, 16).map_err(|_| {
-                    format!("Cannot parse hex for escape character 0x{}", unicode_hex)
-                })?;
-                return ::std::char::from_u32(unicode)
-                    .ok_or(format!("Cannot escape character \\u{}", unicode_hex));
-            }
-            't' => return Ok('\t'),
-            'n' => return Ok('\n'),
-            'r' => return Ok('\r'),
-            '\'' => return Ok('\''),
-            '"' => return Ok('"'),
-            '\\' => return Ok('\''),
-            _ => return Err(format!("Unknown escape character '{}'", c)),
+                Err(_) => {
+                    return Err(DovahkiinError::lex(format!(
+                        "Invalid hex escape \\x{}",
+                        hex
+                    )))
+                }
+            }
+        }
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        '0' => '\0',
+        '\'' => '\'',
+        '"' => '"',
+        '\\' => '\\',
+        '\n' => {
+            while let Some(' ') | Some('\t') | Some('\r') | Some('\n') = iter.peek_next() {
+                iter.next();
+            }
+            return Ok(None);
+        }
+        _ => {
+            return Err(DovahkiinError::lex(format!(
+                "Unknown escape character '{}'",
+                c
+            )))
         }
+    };
+    Ok(Some(escaped))
+}
+
+// \u{1F600}, up to 6 hex digits in braces
+fn read_unicode_escape(iter: &mut CharIter) -> Result<char, DovahkiinError> {
+    if iter.next() != Some('{') {
+        return Err(DovahkiinError::lex(
+            "Expect '{' after \\u in unicode escape".to_string(),
+        ));
     }
-    return Err("Unexpected EOF".to_string());
+    let mut hex = String::new();
+    loop {
+        match iter.next() {
+            Some('}') => break,
+            Some(c) if c.is_digit(16) && hex.len() < 6 => hex.push(c),
+            Some(c) => {
+                return Err(DovahkiinError::lex(format!(
+                    "Unexpected '{}' in unicode escape",
+                    c
+                )))
+            }
+            None => {
+                return Err(DovahkiinError::incomplete(
+                    "Unterminated string".to_string(),
+                ))
+            }
+        }
+    }
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(::std::char::from_u32)
+        .ok_or(DovahkiinError::lex(format!(
+            "Invalid unicode escape \\u{{{}}}",
+            hex
+        )))
+}
+
+fn unterminated_string(start: Position) -> DovahkiinError {
+    DovahkiinError::incomplete("Unterminated string".to_string()).with_span(Span::at(start))
 }
 
-fn read_string(iter: &mut CharIter) -> Result<Token, String> {
+// Contents of a quoted string, the iterator should be at the opening quote
+fn read_string_chars(iter: &mut CharIter, is_bytes: bool) -> Result<Vec<char>, DovahkiinError> {
+    let start = iter.position();
     let mut chars = Vec::new();
     while let Some(c) = iter.next() {
         match c {
             '\\' => {
                 // escaping
-                chars.push(read_escaped_char(iter)?);
+                if let Some(c) = read_escaped_char(iter, is_bytes)? {
+                    chars.push(c);
+                }
             }
             '"' => {
-                break;
+                iter.next();
+                return Ok(chars);
+            }
+            _ if is_bytes && !c.is_ascii() => {
+                return Err(DovahkiinError::lex(format!(
+                    "Non-ASCII character '{}' in byte string, use \\x escapes",
+                    c
+                )))
             }
             _ => {
                 chars.push(c);
             }
         }
     }
+    Err(unterminated_string(start))
+}
+
+fn read_string(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let chars = read_string_chars(iter, false)?;
     return Ok(Token::String(chars.into_iter().collect()));
 }
 
-fn read_symbol(first: char, iter: &mut CharIter) -> Result<Token, String> {
-    let mut chars = vec![first];
+// b"...", chars are all in 0..=0xff here
+fn read_byte_string(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    iter.next();
+    let chars = read_string_chars(iter, true)?;
+    return Ok(Token::Bytes(chars.into_iter().map(|c| c as u8).collect()));
+}
+
+// r"..." or r#"..."# with any number of '#', nothing is escaped inside
+fn read_raw_string(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let start = iter.position();
+    let mut hashes = 0;
+    let mut next = iter.next();
+    while next == Some('#') {
+        hashes += 1;
+        next = iter.next();
+    }
+    if next != Some('"') {
+        return Err(DovahkiinError::lex(
+            "Expect '\"' to start raw string".to_string(),
+        ));
+    }
+    let mut chars = Vec::new();
     while let Some(c) = iter.next() {
-        match c {
-            ' ' | '\t' | '\r' | '\n' | '(' | ')' | '[' | ']' | '\'' => {
-                break;
+        if c == '"' {
+            let mut closing = 0;
+            while closing < hashes && iter.peek_next() == Some('#') {
+                iter.next();
+                closing += 1;
             }
-            _ => {
+            if closing == hashes {
+                iter.next();
+                return Ok(Token::String(chars.into_iter().collect()));
+            }
+            chars.push('"');
+            chars.extend(::std::iter::repeat('#').take(closing));
+        } else {
+            chars.push(c);
+        }
+    }
+    Err(unterminated_string(start))
+}
+
+fn is_delimiter(c: char) -> bool {
+    match c {
+        ' ' | '\t' | '\r' | '\n' | '(' | ')' | '[' | ']' | '{' | '}' | '\'' | ';' => true,
+        _ => false,
+    }
+}
+
+// \a, \newline or \u{1F600}. The char right after '\' is always taken, so \( and \; work.
+fn read_char(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let mut chars = match iter.next() {
+        Some(c) => vec![c],
+        None => {
+            return Err(DovahkiinError::incomplete(
+                "Unexpected EOF, expect a character after '\\'".to_string(),
+            ))
+        }
+    };
+    while let Some(c) = iter.next() {
+        if c == '{' && chars == ['u'] {
+            // braces of \u{...} are delimiters for other tokens
+            chars.push(c);
+            while let Some(c) = iter.next() {
                 chars.push(c);
+                if c == '}' {
+                    iter.next();
+                    break;
+                }
             }
+            break;
+        }
+        if is_delimiter(c) {
+            break;
         }
+        chars.push(c);
+    }
+    if chars.len() == 1 {
+        return Ok(Token::Char(chars[0]));
+    }
+    let name: String = chars.into_iter().collect();
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|&&(n, _)| n == name) {
+        return Ok(Token::Char(c));
+    }
+    if name.starts_with("u{") && name.ends_with('}') {
+        let hex = &name[2..name.len() - 1];
+        return u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(::std::char::from_u32)
+            .map(Token::Char)
+            .ok_or(DovahkiinError::lex(format!(
+                "Invalid unicode character \\{}",
+                name
+            )));
     }
-    return Ok(Token::Symbol(chars.into_iter().collect()));
+    Err(DovahkiinError::lex(format!(
+        "Unknown character name \\{}",
+        name
+    )))
 }
 
-pub fn tokenize_chars_iter(iter: &mut CharIter) -> Result<Vec<Token>, String> {
-    let mut tokens = Vec::new();
+fn read_name(first: char, iter: &mut CharIter) -> String {
+    let mut chars = vec![first];
+    while let Some(c) = iter.next() {
+        if is_delimiter(c) {
+            break;
+        }
+        chars.push(c);
+    }
+    chars.into_iter().collect()
+}
+
+fn read_symbol(first: char, iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    return Ok(Token::Symbol(read_name(first, iter)));
+}
+
+fn read_keyword(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let mut chars = Vec::new();
+    while let Some(c) = iter.next() {
+        if is_delimiter(c) {
+            break;
+        }
+        chars.push(c);
+    }
+    return Ok(Token::Keyword(chars.into_iter().collect()));
+}
+
+fn read_line_comment(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let mut chars = Vec::new();
+    while let Some(c) = iter.next() {
+        if c == '\n' {
+            break;
+        }
+        chars.push(c);
+    }
+    return Ok(Token::LineComment(chars.into_iter().collect()));
+}
+
+fn read_block_comment(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    // block comments can be nested, current char is the '|' of the opening '#|'
+    let mut chars = Vec::new();
+    let mut depth = 1;
+    while let Some(c) = iter.next() {
+        match (c, iter.peek_next()) {
+            ('|', Some('#')) | ('#', Some('|')) => {
+                depth += if c == '#' { 1 } else { -1 };
+                if depth == 0 {
+                    iter.next();
+                    iter.next();
+                    return Ok(Token::BlockComment(chars.into_iter().collect()));
+                }
+                chars.push(c);
+                chars.push(iter.next().unwrap());
+            }
+            _ => chars.push(c),
+        }
+    }
+    return Err(DovahkiinError::incomplete(
+        "Unexpected EOF, expect '|#' for block comment".to_string(),
+    ));
+}
+
+fn read_dispatch(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    match iter.peek_next() {
+        Some('|') => {
+            iter.next();
+            read_block_comment(iter)
+        }
+        Some('_') => {
+            iter.next();
+            iter.next();
+            Ok(Token::DatumComment)
+        }
+        // tagged literals like #id "..." or #pos2d [...]
+        Some(c) if c.is_alphabetic() => {
+            iter.next();
+            Ok(Token::Tag(read_name(c, iter)))
+        }
+        _ => read_symbol('#', iter),
+    }
+}
+
+fn error_at(err: DovahkiinError, iter: &CharIter) -> DovahkiinError {
+    err.or_span(Span::at(iter.position()))
+}
+
+/// Reads the next token, None at the end of input
+pub fn next_token(iter: &mut CharIter) -> Result<Option<SpannedToken>, DovahkiinError> {
+    let token = read_token(iter);
+    // a failed read looks like EOF to the lexer, report the io error instead
+    match iter.read_error.take() {
+        Some(e) => Err(
+            DovahkiinError::new(ErrorKind::Io, format!("Cannot read input, {}", e))
+                .with_span(Span::at(iter.position())),
+        ),
+        None => token,
+    }
+}
+
+fn read_token(iter: &mut CharIter) -> Result<Option<SpannedToken>, DovahkiinError> {
+    iter.compact();
     while let Some(c) = iter.current() {
-        match c {
+        let start = iter.position();
+        let token = match c {
             ' ' | '\t' | '\r' | '\n' => {
                 // whitespaces
                 // will do nothing
                 readout_whitespaces(iter);
+                continue;
             }
             '(' => {
-                tokens.push(Token::LeftParentheses);
                 iter.next();
+                Token::LeftParentheses
             }
             ')' => {
-                tokens.push(Token::RightParentheses);
                 iter.next();
+                Token::RightParentheses
             }
             '[' => {
-                tokens.push(Token::LeftVecParentheses);
                 iter.next();
+                Token::LeftVecParentheses
             }
             ']' => {
-                tokens.push(Token::RightVecParentheses);
                 iter.next();
+                Token::RightVecParentheses
             }
-            NUMBER_PATTERN!() => {
-                tokens.push(read_number(c, iter)?);
+            '{' => {
+                iter.next();
+                Token::LeftMapParentheses
             }
+            '}' => {
+                iter.next();
+                Token::RightMapParentheses
+            }
+            NUMBER_PATTERN!() => read_number(c, iter).map_err(|e| error_at(e, iter))?,
             // match negative number need next char to be a digit
             '-' if match iter.peek_next() {
                 Some(NUMBER_PATTERN!()) => true,
                 _ => false,
             } =>
             {
-                tokens.push(read_number(c, iter)?);
+                read_number(c, iter).map_err(|e| error_at(e, iter))?
+            }
+            '\'' => {
+                iter.next();
+                Token::Quote
+            }
+            '`' => {
+                iter.next();
+                Token::QuasiQuote
+            }
+            '~' => {
+                if iter.next() == Some('@') {
+                    iter.next();
+                    Token::UnquoteSplicing
+                } else {
+                    Token::Unquote
+                }
             }
-            // '\'' => { // quote
-            //     tokens.push(Token::Quote);
-            //     iter.next();
-            // },
             '"' => {
                 // string
-                tokens.push(read_string(iter)?);
+                read_string(iter).map_err(|e| error_at(e, iter))?
+            }
+            'r' if match iter.peek_next() {
+                Some('"') | Some('#') => true,
+                _ => false,
+            } =>
+            {
+                read_raw_string(iter).map_err(|e| error_at(e, iter))?
             }
+            'b' if iter.peek_next() == Some('"') => {
+                read_byte_string(iter).map_err(|e| error_at(e, iter))?
+            }
+            '\\' => read_char(iter).map_err(|e| error_at(e, iter))?,
+            ';' => read_line_comment(iter)?,
+            ':' if iter.peek_next().map(|c| !is_delimiter(c)).unwrap_or(false) => {
+                read_keyword(iter)?
+            }
+            '#' => read_dispatch(iter).map_err(|e| error_at(e, iter))?,
             _ => {
                 // symbol with utf8 chars including emojis
-                tokens.push(read_symbol(c, iter)?);
+                read_symbol(c, iter).map_err(|e| error_at(e, iter))?
             }
-        }
+        };
+        return Ok(Some(SpannedToken {
+            token,
+            span: Span::new(start, iter.position()),
+        }));
+    }
+    return Ok(None);
+}
+
+pub fn tokenize_chars_iter(iter: &mut CharIter) -> Result<Vec<SpannedToken>, DovahkiinError> {
+    let mut tokens = Vec::new();
+    while let Some(token) = next_token(iter)? {
+        tokens.push(token);
     }
     return Ok(tokens);
 }
 
-pub fn tokenize_str<'a>(str: &'a str) -> Result<Vec<Token>, String> {
+pub fn tokenize_str<'a>(str: &'a str) -> Result<Vec<SpannedToken>, DovahkiinError> {
     let mut iter = CharIter::new(str.chars().collect());
     tokenize_chars_iter(&mut iter)
 }
+
+/// Tokens read one by one from a reader, so large inputs are never held in memory.
+/// Stops after the first error.
+pub struct TokenReader<'a> {
+    iter: CharIter<'a>,
+    failed: bool,
+}
+
+impl<'a> TokenReader<'a> {
+    pub fn new<R: BufRead + 'a>(reader: R) -> TokenReader<'a> {
+        TokenReader {
+            iter: CharIter::from_reader(reader),
+            failed: false,
+        }
+    }
+}
+
+impl<'a> Iterator for TokenReader<'a> {
+    type Item = Result<SpannedToken, DovahkiinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match next_token(&mut self.iter) {
+            Ok(token) => token.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
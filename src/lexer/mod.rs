@@ -1 +1,2 @@
 pub mod lisp;
+pub mod sql;
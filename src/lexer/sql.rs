@@ -0,0 +1,213 @@
+use error::DovahkiinError;
+use lexer::lisp::CharIter;
+use span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // names and keywords, keywords are matched case-insensitively by the parser
+    Ident(String),
+    // "name", never a keyword
+    QuotedIdent(String),
+    IntNumber(String, String),
+    FloatNumber(String, String),
+    String(String),
+    LeftParentheses,
+    RightParentheses,
+    Comma,
+    Dot,
+    Semicolon,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Equals,
+    NotEquals,
+    LessThan,
+    LessThanEquals,
+    GreaterThan,
+    GreaterThanEquals,
+}
+
+impl ToString for Token {
+    fn to_string(&self) -> String {
+        match self {
+            &Token::Ident(ref name) => name.clone(),
+            &Token::QuotedIdent(ref name) => format!("\"{}\"", name.replace('"', "\"\"")),
+            &Token::IntNumber(ref n, ref u) | &Token::FloatNumber(ref n, ref u) => {
+                format!("{}{}", n, u)
+            }
+            &Token::String(ref str) => format!("'{}'", str.replace('\'', "''")),
+            &Token::LeftParentheses => String::from("("),
+            &Token::RightParentheses => String::from(")"),
+            &Token::Comma => String::from(","),
+            &Token::Dot => String::from("."),
+            &Token::Semicolon => String::from(";"),
+            &Token::Star => String::from("*"),
+            &Token::Plus => String::from("+"),
+            &Token::Minus => String::from("-"),
+            &Token::Slash => String::from("/"),
+            &Token::Equals => String::from("="),
+            &Token::NotEquals => String::from("!="),
+            &Token::LessThan => String::from("<"),
+            &Token::LessThanEquals => String::from("<="),
+            &Token::GreaterThan => String::from(">"),
+            &Token::GreaterThanEquals => String::from(">="),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn read_while<F: Fn(char) -> bool>(iter: &mut CharIter, pred: F) -> String {
+    let mut str = String::new();
+    while let Some(c) = iter.current() {
+        if !pred(c) {
+            break;
+        }
+        str.push(c);
+        iter.next();
+    }
+    str
+}
+
+// 'text' and "name", the quote is doubled to write it inside
+fn read_quoted(iter: &mut CharIter, quote: char) -> Result<String, DovahkiinError> {
+    let start = iter.position();
+    let mut str = String::new();
+    iter.next();
+    loop {
+        match iter.current() {
+            Some(c) if c == quote => {
+                if iter.peek_next() != Some(quote) {
+                    iter.next();
+                    return Ok(str);
+                }
+                str.push(quote);
+                iter.next();
+            }
+            Some(c) => str.push(c),
+            None => {
+                return Err(
+                    DovahkiinError::incomplete(format!("Unexpected EOF, expect {}", quote))
+                        .with_span(Span::at(start)),
+                )
+            }
+        }
+        iter.next();
+    }
+}
+
+// digits with an optional fraction, exponent and type unit like 1u32 or 2.5f32
+fn read_number(iter: &mut CharIter) -> Result<Token, DovahkiinError> {
+    let mut num = read_while(iter, |c| c.is_ascii_digit());
+    let mut is_float = false;
+    if iter.current() == Some('.') && iter.peek_next().map_or(false, |c| c.is_ascii_digit()) {
+        iter.next();
+        num.push('.');
+        num.push_str(&read_while(iter, |c| c.is_ascii_digit()));
+        is_float = true;
+    }
+    if let Some('e') | Some('E') = iter.current() {
+        let exponent_pos = iter.position();
+        num.push('e');
+        iter.next();
+        if let Some(sign @ '+') | Some(sign @ '-') = iter.current() {
+            num.push(sign);
+            iter.next();
+        }
+        let digits = read_while(iter, |c| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Err(
+                DovahkiinError::lex(format!("Expect digits in exponent of {}", num))
+                    .with_span(Span::at(exponent_pos)),
+            );
+        }
+        num.push_str(&digits);
+        is_float = true;
+    }
+    let unit = read_while(iter, |c| c.is_alphanumeric() || c == '_');
+    Ok(if is_float || unit.starts_with('f') {
+        Token::FloatNumber(num, unit)
+    } else {
+        Token::IntNumber(num, unit)
+    })
+}
+
+fn read_token(iter: &mut CharIter) -> Result<Option<SpannedToken>, DovahkiinError> {
+    loop {
+        match iter.current() {
+            Some(c) if c.is_whitespace() => {
+                iter.next();
+            }
+            // -- comment till the end of line
+            Some('-') if iter.peek_next() == Some('-') => {
+                read_while(iter, |c| c != '\n');
+            }
+            _ => break,
+        }
+    }
+    let start = iter.position();
+    let c = match iter.current() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let token = match c {
+        '\'' => Token::String(read_quoted(iter, '\'')?),
+        '"' => Token::QuotedIdent(read_quoted(iter, '"')?),
+        _ if c.is_ascii_digit() => read_number(iter)?,
+        _ if is_ident_start(c) => Token::Ident(read_while(iter, is_ident_char)),
+        _ => {
+            let next = iter.peek_next();
+            let (token, len) = match (c, next) {
+                ('!', Some('=')) | ('<', Some('>')) => (Token::NotEquals, 2),
+                ('<', Some('=')) => (Token::LessThanEquals, 2),
+                ('>', Some('=')) => (Token::GreaterThanEquals, 2),
+                ('<', _) => (Token::LessThan, 1),
+                ('>', _) => (Token::GreaterThan, 1),
+                ('=', _) => (Token::Equals, 1),
+                ('(', _) => (Token::LeftParentheses, 1),
+                (')', _) => (Token::RightParentheses, 1),
+                (',', _) => (Token::Comma, 1),
+                ('.', _) => (Token::Dot, 1),
+                (';', _) => (Token::Semicolon, 1),
+                ('*', _) => (Token::Star, 1),
+                ('+', _) => (Token::Plus, 1),
+                ('-', _) => (Token::Minus, 1),
+                ('/', _) => (Token::Slash, 1),
+                _ => {
+                    return Err(DovahkiinError::lex(format!("Unexpected character {:?}", c))
+                        .with_span(Span::at(start)))
+                }
+            };
+            for _ in 0..len {
+                iter.next();
+            }
+            token
+        }
+    };
+    Ok(Some(SpannedToken {
+        token,
+        span: Span::new(start, iter.position()),
+    }))
+}
+
+pub fn tokenize_str(str: &str) -> Result<Vec<SpannedToken>, DovahkiinError> {
+    let mut iter = CharIter::new(str.chars().collect());
+    let mut tokens = Vec::new();
+    while let Some(token) = read_token(&mut iter)? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
@@ -9,12 +9,16 @@ extern crate serde_derive;
 extern crate bifrost;
 extern crate bifrost_hasher;
 extern crate byteorder;
+extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
 
 #[macro_use]
 pub mod types;
+pub mod error;
 pub mod expr;
 pub mod integrated;
 pub mod lexer;
 pub mod parser;
+pub mod printer;
+pub mod span;
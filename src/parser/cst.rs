@@ -0,0 +1,215 @@
+use error::DovahkiinError;
+use lexer::lisp::{tokenize_str, SpannedToken, Token};
+use span::Span;
+use std::vec::IntoIter;
+
+/// A token with the exact text it was read from
+#[derive(Debug)]
+pub struct Leaf {
+    pub token: Token,
+    pub text: String,
+    pub span: Span,
+}
+
+/// Lossless syntax tree, printing all nodes back with `to_source` gives the original text.
+/// Unlike `parser::lisp`, comments, whitespace and the spelling of literals are kept.
+#[derive(Debug)]
+pub enum Node {
+    Whitespace(String),
+    // line and block comments
+    Comment(Leaf),
+    Atom(Leaf),
+    // (...), [...] and {...}
+    Group {
+        open: Leaf,
+        children: Vec<Node>,
+        close: Leaf,
+    },
+    // quotes, '#_' and tags. Children are trivia followed by the prefixed form.
+    Prefixed {
+        prefix: Leaf,
+        children: Vec<Node>,
+    },
+}
+
+impl Node {
+    pub fn write_source(&self, out: &mut String) {
+        match self {
+            &Node::Whitespace(ref text) => out.push_str(text),
+            &Node::Comment(ref leaf) | &Node::Atom(ref leaf) => out.push_str(&leaf.text),
+            &Node::Group {
+                ref open,
+                ref children,
+                ref close,
+            } => {
+                out.push_str(&open.text);
+                for child in children {
+                    child.write_source(out);
+                }
+                out.push_str(&close.text);
+            }
+            &Node::Prefixed {
+                ref prefix,
+                ref children,
+            } => {
+                out.push_str(&prefix.text);
+                for child in children {
+                    child.write_source(out);
+                }
+            }
+        }
+    }
+
+    /// Whitespace and comments
+    pub fn is_trivia(&self) -> bool {
+        match self {
+            &Node::Whitespace(_) | &Node::Comment(_) => true,
+            _ => false,
+        }
+    }
+}
+
+pub fn to_source(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        node.write_source(&mut out);
+    }
+    out
+}
+
+struct CstParser {
+    chars: Vec<char>,
+    tokens: IntoIter<SpannedToken>,
+    // offset of the first char not in any node yet
+    pos: usize,
+}
+
+fn closing_of(open: &Token) -> &'static str {
+    match open {
+        &Token::LeftParentheses => ")",
+        &Token::LeftVecParentheses => "]",
+        _ => "}",
+    }
+}
+
+impl CstParser {
+    fn whitespace_before(&mut self, offset: usize, nodes: &mut Vec<Node>) {
+        if offset > self.pos {
+            nodes.push(Node::Whitespace(
+                self.chars[self.pos..offset].iter().collect(),
+            ));
+            self.pos = offset;
+        }
+    }
+
+    fn leaf(&mut self, spanned: SpannedToken) -> Leaf {
+        let (start, end) = (spanned.span.start.offset, spanned.span.end.offset);
+        self.pos = end;
+        Leaf {
+            token: spanned.token,
+            text: self.chars[start..end].iter().collect(),
+            span: spanned.span,
+        }
+    }
+
+    // trivia before the next token is pushed to nodes, and the token is returned
+    fn next_token(&mut self, nodes: &mut Vec<Node>) -> Option<SpannedToken> {
+        let spanned = self.tokens.next()?;
+        self.whitespace_before(spanned.span.start.offset, nodes);
+        Some(spanned)
+    }
+
+    fn node(&mut self, spanned: SpannedToken) -> Result<Node, DovahkiinError> {
+        match spanned.token {
+            Token::LeftParentheses | Token::LeftVecParentheses | Token::LeftMapParentheses => {
+                let open = self.leaf(spanned);
+                let close = closing_of(&open.token);
+                let mut children = Vec::new();
+                loop {
+                    let spanned = match self.next_token(&mut children) {
+                        Some(spanned) => spanned,
+                        None => {
+                            return Err(DovahkiinError::incomplete(format!(
+                                "Unexpected EOF, expect '{}'",
+                                close
+                            ))
+                            .with_span(open.span))
+                        }
+                    };
+                    match spanned.token {
+                        Token::RightParentheses
+                        | Token::RightVecParentheses
+                        | Token::RightMapParentheses => {
+                            if spanned.token.to_string() != close {
+                                return Err(DovahkiinError::parse(format!(
+                                    "Expect '{}', found {}",
+                                    close,
+                                    spanned.token.to_string()
+                                ))
+                                .with_span(spanned.span));
+                            }
+                            return Ok(Node::Group {
+                                open,
+                                children,
+                                close: self.leaf(spanned),
+                            });
+                        }
+                        _ => children.push(self.node(spanned)?),
+                    }
+                }
+            }
+            Token::Quote
+            | Token::QuasiQuote
+            | Token::Unquote
+            | Token::UnquoteSplicing
+            | Token::DatumComment
+            | Token::Tag(_) => {
+                let prefix = self.leaf(spanned);
+                let mut children = Vec::new();
+                loop {
+                    let spanned = match self.next_token(&mut children) {
+                        Some(spanned) => spanned,
+                        None => {
+                            return Err(DovahkiinError::incomplete(format!(
+                                "Unexpected EOF, expect a form after {}",
+                                prefix.text
+                            ))
+                            .with_span(prefix.span))
+                        }
+                    };
+                    let node = self.node(spanned)?;
+                    let is_form = !node.is_trivia();
+                    children.push(node);
+                    if is_form {
+                        return Ok(Node::Prefixed { prefix, children });
+                    }
+                }
+            }
+            Token::RightParentheses | Token::RightVecParentheses | Token::RightMapParentheses => {
+                Err(
+                    DovahkiinError::parse(format!("Unexpected {}", spanned.token.to_string()))
+                        .with_span(spanned.span),
+                )
+            }
+            Token::LineComment(_) | Token::BlockComment(_) => Ok(Node::Comment(self.leaf(spanned))),
+            _ => Ok(Node::Atom(self.leaf(spanned))),
+        }
+    }
+}
+
+/// Reads the source into a lossless syntax tree
+pub fn parse_cst(code: &str) -> Result<Vec<Node>, DovahkiinError> {
+    let mut parser = CstParser {
+        chars: code.chars().collect(),
+        tokens: tokenize_str(code)?.into_iter(),
+        pos: 0,
+    };
+    let mut nodes = Vec::new();
+    while let Some(spanned) = parser.next_token(&mut nodes) {
+        let node = parser.node(spanned)?;
+        nodes.push(node);
+    }
+    let end = parser.chars.len();
+    parser.whitespace_before(end, &mut nodes);
+    Ok(nodes)
+}
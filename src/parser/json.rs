@@ -0,0 +1,237 @@
+use error::DovahkiinError;
+use expr::SExpr;
+use parser::lisp::{
+    float_value, int_value, parse_float, parse_int, parse_keyword, parse_string, parse_symbol,
+    tagged_value, ParserOptions,
+};
+use serde_json::{Map, Number, Value as Json};
+use types::OwnedValue as Value;
+use types::{get_id_type, get_type_id};
+
+// JSON form of expressions, for clients building queries as data instead of lisp text.
+//
+//   "name"                        symbol
+//   [head, args...]               list, like (head args...)
+//   1, 2.5                        number without unit, typed like unsuffixed lisp literals
+//   true, false, null             bool and nil
+//   {"u32": 1}, {"string": "s"}   typed values, the key is a type name like in `types`.
+//                                 {"id": "1-ff"}, {"bytes": "<base64>"} and {"pos2d32": [1, 2]}
+//                                 take the form of the lisp tagged literals
+//   {"$keyword": "name"}          keyword, :name
+//   {"$vec": [items...]}          vector, [items...]
+//   {"$map": {"k": v, ":k": v}}   map literal, keys starting with ':' are keywords
+//   {"let": [args...]}            same as ["let", args...]. For binding forms the binding
+//                                 vector may be a plain array: {"let": [["x", 1], "x"]}
+//
+// The '$' keeps the literal keys apart from calls, {"map": ["f", "xs"]} is (map f xs).
+// Type names always read as values, casts are written as lists like ["u32", "x"].
+
+pub(crate) const KEYWORD_KEY: &str = "$keyword";
+pub(crate) const VEC_KEY: &str = "$vec";
+pub(crate) const MAP_KEY: &str = "$map";
+
+// Position of the binding vector in forms like (let [x 1] ...) and (defunc f [x] ...)
+pub(crate) fn binding_position(head: &str) -> Option<usize> {
+    match head {
+        "let" | "lambda" => Some(0),
+        "defunc" => Some(1),
+        _ => None,
+    }
+}
+
+fn invalid(path: &str, message: String) -> DovahkiinError {
+    let path = if path.is_empty() { "/" } else { path };
+    DovahkiinError::parse(format!("{}, at {}", message, path))
+}
+
+fn parse_number(
+    num: &Number,
+    path: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    if num.is_f64() {
+        parse_float(num.to_string(), String::new(), options)
+    } else {
+        parse_int(num.to_string(), String::new(), options)
+    }
+    .map_err(|e| invalid(path, e.message))
+}
+
+fn parse_items(
+    items: &[Json],
+    path: &str,
+    options: &ParserOptions,
+) -> Result<Vec<SExpr>, DovahkiinError> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| parse_json_at(item, &format!("{}/{}", path, i), options))
+        .collect()
+}
+
+fn parse_map(
+    entries: &Map<String, Json>,
+    path: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let mut contents = vec![parse_symbol("hash-map".to_string())];
+    for (key, value) in entries {
+        contents.push(if key.starts_with(':') {
+            parse_keyword(key[1..].to_string())
+        } else {
+            parse_string(key.clone())
+        });
+        contents.push(parse_json_at(value, &format!("{}/{}", path, key), options)?);
+    }
+    Ok(SExpr::List(contents))
+}
+
+// Number, string or array literal taken by a typed value, like the form after a lisp tag
+fn literal_form(json: &Json, path: &str, options: &ParserOptions) -> Result<SExpr, DovahkiinError> {
+    match json {
+        &Json::Number(ref num) => parse_number(num, path, options),
+        &Json::String(ref str) => Ok(parse_string(str.clone())),
+        &Json::Array(ref items) => Ok(SExpr::Vec(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| literal_form(item, &format!("{}/{}", path, i), options))
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Ok(SExpr::Value(Value::Null)),
+    }
+}
+
+fn typed_value(
+    type_id: u32,
+    json: &Json,
+    path: &str,
+    options: &ParserOptions,
+) -> Result<Value, DovahkiinError> {
+    let name = get_id_type(type_id);
+    let value = match (name, json) {
+        ("bool", &Json::Bool(b)) => Some(Value::Bool(b)),
+        ("char", &Json::String(ref str)) => {
+            let mut chars = str.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Value::Char(c)),
+                _ => None,
+            }
+        }
+        ("string", &Json::String(ref str)) => Some(Value::String(str.clone())),
+        ("f32", &Json::Number(ref num)) | ("f64", &Json::Number(ref num)) => {
+            Some(float_value(&num.to_string(), name).map_err(|e| invalid(path, e.message))?)
+        }
+        ("i8", &Json::Number(ref num))
+        | ("i16", &Json::Number(ref num))
+        | ("i32", &Json::Number(ref num))
+        | ("i64", &Json::Number(ref num))
+        | ("u8", &Json::Number(ref num))
+        | ("u16", &Json::Number(ref num))
+        | ("u32", &Json::Number(ref num))
+        | ("u64", &Json::Number(ref num))
+            if !num.is_f64() =>
+        {
+            Some(int_value(&num.to_string(), name).map_err(|e| invalid(path, e.message))?)
+        }
+        ("bool", _)
+        | ("char", _)
+        | ("string", _)
+        | ("f32", _)
+        | ("f64", _)
+        | ("i8", _)
+        | ("i16", _)
+        | ("i32", _)
+        | ("i64", _)
+        | ("u8", _)
+        | ("u16", _)
+        | ("u32", _)
+        | ("u64", _) => None,
+        _ => tagged_value(name, &literal_form(json, path, options)?)
+            .map_err(|e| invalid(path, e.message))?,
+    };
+    value.ok_or_else(|| invalid(path, format!("Invalid value for {}, found {}", name, json)))
+}
+
+fn parse_object(
+    entries: &Map<String, Json>,
+    path: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let (key, json) = match entries.iter().next() {
+        Some(entry) if entries.len() == 1 => entry,
+        _ => {
+            return Err(invalid(
+                path,
+                format!(
+                    "Object form should have exactly one key, found {}",
+                    entries.len()
+                ),
+            ))
+        }
+    };
+    let path = format!("{}/{}", path, key);
+    let type_id = get_type_id(key.clone());
+    match (key.as_ref(), json) {
+        (KEYWORD_KEY, &Json::String(ref name)) => Ok(parse_keyword(name.clone())),
+        (VEC_KEY, &Json::Array(ref items)) => Ok(SExpr::Vec(parse_items(items, &path, options)?)),
+        (MAP_KEY, &Json::Object(ref entries)) => parse_map(entries, &path, options),
+        (KEYWORD_KEY, _) | (VEC_KEY, _) | (MAP_KEY, _) => Err(invalid(
+            &path,
+            format!("Invalid form for {}, found {}", key, json),
+        )),
+        _ if type_id != 0 => typed_value(type_id, json, &path, options).map(SExpr::Value),
+        (_, &Json::Array(ref args)) => {
+            let mut contents = vec![parse_symbol(key.clone())];
+            for (i, arg) in args.iter().enumerate() {
+                let arg_path = format!("{}/{}", path, i);
+                contents.push(match arg {
+                    &Json::Array(ref items) if binding_position(key) == Some(i) => {
+                        SExpr::Vec(parse_items(items, &arg_path, options)?)
+                    }
+                    _ => parse_json_at(arg, &arg_path, options)?,
+                });
+            }
+            Ok(SExpr::List(contents))
+        }
+        _ => Err(invalid(
+            &path,
+            format!("Expect an array of arguments for {}, found {}", key, json),
+        )),
+    }
+}
+
+fn parse_json_at(
+    json: &Json,
+    path: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    match json {
+        &Json::Null => Ok(SExpr::Value(Value::Null)),
+        &Json::Bool(b) => Ok(SExpr::Value(Value::Bool(b))),
+        &Json::Number(ref num) => parse_number(num, path, options),
+        &Json::String(ref name) => Ok(parse_symbol(name.clone())),
+        &Json::Array(ref items) => Ok(SExpr::List(parse_items(items, path, options)?)),
+        &Json::Object(ref entries) => parse_object(entries, path, options),
+    }
+}
+
+/// Lowers a JSON form to the expression the lisp parser reads from the same code.
+/// Errors name the path of the invalid form, like /let/1/0.
+pub fn parse_json(json: &Json, options: &ParserOptions) -> Result<SExpr, DovahkiinError> {
+    parse_json_at(json, "", options)
+}
+
+/// Reads one JSON form from the text
+pub fn parse_to_sexpr(code: &str) -> Result<SExpr, DovahkiinError> {
+    parse_to_sexpr_with_options(code, &ParserOptions::default())
+}
+
+pub fn parse_to_sexpr_with_options(
+    code: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let json: Json = ::serde_json::from_str(code)
+        .map_err(|e| DovahkiinError::parse(format!("Invalid JSON, {}", e)))?;
+    parse_json(&json, options)
+}
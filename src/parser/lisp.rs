@@ -1,100 +1,507 @@
 use bifrost_hasher::hash_str;
+use error::DovahkiinError;
 use expr::SExpr;
-use lexer::lisp::Token;
-use std::vec::IntoIter;
+use lexer::lisp::{SpannedToken, Token};
+use span::Span;
+use std::num::IntErrorKind;
 use types::OwnedValue as Value;
+use types::{get_id_type, get_type_id, key_hash, type_id_of, Type};
+use types::{Bytes, Id, Pos2d32, Pos2d64, Pos3d32, Pos3d64};
 
-fn parse_list(iter: &mut IntoIter<Token>) -> Result<SExpr, String> {
+// tokens may come from a reader, so reading the next one can fail
+type Tokens<'a> = dyn Iterator<Item = Result<SpannedToken, DovahkiinError>> + 'a;
+
+#[derive(Clone, Copy)]
+pub struct ParserOptions {
+    // types for number literals without unit, like 1 or 2.5
+    pub default_int_type: Type,
+    pub default_float_type: Type,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            default_int_type: Type::I64,
+            default_float_type: Type::F64,
+        }
+    }
+}
+
+// Next token which is not a comment. Forms after '#_' are parsed and discarded here.
+fn next_token(
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<Option<SpannedToken>, DovahkiinError> {
+    while let Some(spanned) = iter.next() {
+        let spanned = spanned?;
+        match spanned.token {
+            Token::LineComment(_) | Token::BlockComment(_) => {}
+            Token::DatumComment => match next_token(iter, options)? {
+                Some(SpannedToken { token, span }) => match token {
+                    Token::RightParentheses
+                    | Token::RightVecParentheses
+                    | Token::RightMapParentheses => {
+                        return Err(DovahkiinError::parse(format!(
+                            "Expect a form to discard after '#_', found {}",
+                            token.to_string()
+                        ))
+                        .with_span(spanned.span));
+                    }
+                    _ => {
+                        parse_token(token, span, iter, options)?;
+                    }
+                },
+                None => {
+                    return Err(DovahkiinError::incomplete(String::from(
+                        "Unexpected EOF, expect a form to discard after '#_'",
+                    ))
+                    .with_span(spanned.span));
+                }
+            },
+            _ => return Ok(Some(spanned)),
+        }
+    }
+    Ok(None)
+}
+
+fn parse_list(
+    open: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
     let mut contents = Vec::new();
-    while let Some(token) = iter.next() {
+    while let Some(SpannedToken { token, span }) = next_token(iter, options)? {
         match token {
             Token::RightParentheses => {
-                return Ok(SExpr::List(contents));
+                return Ok(SExpr::Located(
+                    open.to(&span),
+                    Box::new(SExpr::List(contents)),
+                ));
             }
             _ => {
-                contents.push(parse_token(token, iter)?);
+                contents.push(parse_token(token, span, iter, options)?);
             }
         }
     }
-    Err(String::from("Unexpected EOF, expect ')'"))
+    Err(DovahkiinError::incomplete(String::from("Unexpected EOF, expect ')'")).with_span(open))
 }
 
-fn parse_vec(iter: &mut IntoIter<Token>) -> Result<SExpr, String> {
+fn parse_vec(
+    open: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
     let mut contents = Vec::new();
-    while let Some(token) = iter.next() {
+    while let Some(SpannedToken { token, span }) = next_token(iter, options)? {
         match token {
             Token::RightVecParentheses => {
                 return Ok(SExpr::Vec(contents));
             }
             _ => {
-                contents.push(parse_token(token, iter)?);
+                contents.push(parse_token(token, span, iter, options)?);
+            }
+        }
+    }
+    Err(DovahkiinError::incomplete(String::from("Unexpected EOF, expect ']'")).with_span(open))
+}
+
+// map literals are read as (hash-map k1 v1 k2 v2 ...), so the values are still evaluated
+fn parse_map(
+    open: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let mut contents = vec![parse_symbol("hash-map".to_string())];
+    let mut is_key = true;
+    while let Some(SpannedToken { token, span }) = next_token(iter, options)? {
+        match token {
+            Token::RightMapParentheses => {
+                if !is_key {
+                    return Err(DovahkiinError::parse(String::from(
+                        "Map literal require even number of forms",
+                    ))
+                    .with_span(open.to(&span)));
+                }
+                return Ok(SExpr::Located(
+                    open.to(&span),
+                    Box::new(SExpr::List(contents)),
+                ));
+            }
+            Token::String(key) if is_key => contents.push(parse_string(key)),
+            Token::Keyword(key) if is_key => contents.push(parse_keyword(key)),
+            _ if is_key => {
+                return Err(DovahkiinError::parse(format!(
+                    "Map key should be a string or keyword, found {}",
+                    token.to_string()
+                ))
+                .with_span(span));
             }
+            _ => contents.push(parse_token(token, span, iter, options)?),
         }
+        is_key = !is_key;
     }
-    Err(String::from("Unexpected EOF, expect ']'"))
+    Err(DovahkiinError::incomplete(String::from("Unexpected EOF, expect '}'")).with_span(open))
+}
+
+// 'x, `x, ~x and ~@x are read as (quote x), (quasiquote x), (unquote x) and (unquote-splicing x)
+fn parse_quote(
+    name: &str,
+    quote_span: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let form = match next_token(iter, options)? {
+        Some(SpannedToken { token, span }) => match token {
+            Token::RightParentheses | Token::RightVecParentheses | Token::RightMapParentheses => {
+                return Err(DovahkiinError::parse(format!(
+                    "Expect a form for {}, found {}",
+                    name,
+                    token.to_string()
+                ))
+                .with_span(span));
+            }
+            _ => parse_token(token, span, iter, options)?,
+        },
+        None => {
+            return Err(DovahkiinError::incomplete(format!(
+                "Unexpected EOF, expect a form for {}",
+                name
+            ))
+            .with_span(quote_span));
+        }
+    };
+    let span = quote_span.to(&form.span().unwrap_or(quote_span));
+    Ok(SExpr::Located(
+        span,
+        Box::new(SExpr::List(vec![parse_symbol(name.to_string()), form])),
+    ))
+}
+
+// Tagged literals like #id "0000000000000001-00000000000000ff" are read into values of the tag type
+fn parse_tagged(
+    name: String,
+    tag_span: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let form = match next_token(iter, options)? {
+        Some(SpannedToken { token, span }) => parse_token(token, span, iter, options)?,
+        None => {
+            return Err(DovahkiinError::incomplete(format!(
+                "Unexpected EOF, expect a form for #{}",
+                name
+            ))
+            .with_span(tag_span));
+        }
+    };
+    let span = tag_span.to(&form.span().unwrap_or(tag_span));
+    let value = tagged_value(&name, &form).map_err(|e| e.with_span(tag_span))?;
+    value.map(SExpr::Value).ok_or(
+        DovahkiinError::parse(format!("Invalid form for #{}, found {:?}", name, form))
+            .with_span(span),
+    )
+}
+
+// Value of the tag type read from the form, None when the form does not fit the tag
+pub(crate) fn tagged_value(name: &str, form: &SExpr) -> Result<Option<Value>, DovahkiinError> {
+    let type_id = get_type_id(name.to_string());
+    Ok(if type_id == type_id_of(Type::Id) {
+        parse_id(form)
+    } else if type_id == type_id_of(Type::Pos2d32) {
+        coordinates(form, 2, false).map(|c| {
+            Value::Pos2d32(Pos2d32 {
+                x: c[0] as f32,
+                y: c[1] as f32,
+            })
+        })
+    } else if type_id == type_id_of(Type::Pos2d64) {
+        coordinates(form, 2, true).map(|c| Value::Pos2d64(Pos2d64 { x: c[0], y: c[1] }))
+    } else if type_id == type_id_of(Type::Pos3d32) {
+        coordinates(form, 3, false).map(|c| {
+            Value::Pos3d32(Pos3d32 {
+                x: c[0] as f32,
+                y: c[1] as f32,
+                z: c[2] as f32,
+            })
+        })
+    } else if type_id == type_id_of(Type::Pos3d64) {
+        coordinates(form, 3, true).map(|c| {
+            Value::Pos3d64(Pos3d64 {
+                x: c[0],
+                y: c[1],
+                z: c[2],
+            })
+        })
+    } else if type_id == type_id_of(Type::Bytes) {
+        match form {
+            &SExpr::Value(Value::String(ref str)) => Bytes::from_base64(str).map(Value::Bytes),
+            _ => None,
+        }
+    } else {
+        return Err(DovahkiinError::parse(format!("Unknown tag #{}", name)));
+    })
+}
+
+// "higher-lower" in hex
+fn parse_id(form: &SExpr) -> Option<Value> {
+    if let &SExpr::Value(Value::String(ref str)) = form {
+        let mut parts = str.split('-');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(higher), Some(lower), None) => {
+                let higher = u64::from_str_radix(higher, 16).ok()?;
+                let lower = u64::from_str_radix(lower, 16).ok()?;
+                Some(Value::Id(Id::new(higher, lower)))
+            }
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+// Numbers in a vector, typed ones should have the float type of the position
+fn coordinates(form: &SExpr, dimensions: usize, is_f64: bool) -> Option<Vec<f64>> {
+    let items = match form {
+        &SExpr::Vec(ref items) if items.len() == dimensions => items,
+        _ => return None,
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            &SExpr::Value(Value::F32(num)) if !is_f64 => Some(num as f64),
+            &SExpr::Value(Value::F64(num)) if is_f64 => Some(num),
+            &SExpr::Untyped(ref literal) => match literal {
+                &Value::F32(num) => Some(num as f64),
+                &Value::F64(num) => Some(num),
+                &Value::I8(num) => Some(num as f64),
+                &Value::I16(num) => Some(num as f64),
+                &Value::I32(num) => Some(num as f64),
+                &Value::I64(num) => Some(num as f64),
+                &Value::U8(num) => Some(num as f64),
+                &Value::U16(num) => Some(num as f64),
+                &Value::U32(num) => Some(num as f64),
+                &Value::U64(num) => Some(num as f64),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
 }
 
-fn parse_symbol(name: String) -> SExpr {
+pub(crate) fn parse_symbol(name: String) -> SExpr {
     SExpr::ISymbol(hash_str(&name), name)
 }
 
-fn parse_int(num_str: String, unit: String) -> Result<SExpr, String> {
-    match unit.as_ref() {
-        "u8" => num_str.parse::<u8>().map(Value::U8),
-        "u16" => num_str.parse::<u16>().map(Value::U16),
-        "u32" => num_str.parse::<u32>().map(Value::U32),
-        "u64" => num_str.parse::<u64>().map(Value::U64),
-        "i8" => num_str.parse::<i8>().map(Value::I8),
-        "i16" => num_str.parse::<i16>().map(Value::I16),
-        "i32" => num_str.parse::<i32>().map(Value::I32),
-        "i64" => num_str.parse::<i64>().map(Value::I64),
-        _ => return Err(format!("Unknown int number type {}", unit)),
+pub(crate) fn parse_keyword(name: String) -> SExpr {
+    SExpr::Keyword(key_hash(&name), name)
+}
+
+pub(crate) fn parse_int(
+    num_str: String,
+    unit: String,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    if unit.is_empty() {
+        let unit = get_id_type(type_id_of(options.default_int_type));
+        return int_value(&num_str, unit).map(SExpr::Untyped);
     }
-    .map_err(|e| {
-        format!(
+    int_value(&num_str, &unit).map(SExpr::Value)
+}
+
+// Drops '_' separators and the radix prefix, -0xFF is read as ("-FF", 16)
+fn number_digits(num_str: &str) -> (String, u32) {
+    let digits: String = num_str.chars().filter(|&c| c != '_').collect();
+    let (sign, unsigned) = if digits.starts_with('-') {
+        ("-", &digits[1..])
+    } else {
+        ("", &digits[..])
+    };
+    let radix = match unsigned.get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => return (digits.clone(), 10),
+    };
+    (format!("{}{}", sign, &unsigned[2..]), radix)
+}
+
+pub(crate) fn int_value(num_str: &str, unit: &str) -> Result<Value, DovahkiinError> {
+    let (digits, radix) = number_digits(num_str);
+    match unit {
+        "u8" => u8::from_str_radix(&digits, radix).map(Value::U8),
+        "u16" => u16::from_str_radix(&digits, radix).map(Value::U16),
+        "u32" => u32::from_str_radix(&digits, radix).map(Value::U32),
+        "u64" => u64::from_str_radix(&digits, radix).map(Value::U64),
+        "i8" => i8::from_str_radix(&digits, radix).map(Value::I8),
+        "i16" => i16::from_str_radix(&digits, radix).map(Value::I16),
+        "i32" => i32::from_str_radix(&digits, radix).map(Value::I32),
+        "i64" => i64::from_str_radix(&digits, radix).map(Value::I64),
+        _ => {
+            return Err(DovahkiinError::parse(format!(
+                "Unknown int number type {}",
+                unit
+            )))
+        }
+    }
+    .map_err(|e| match e.kind() {
+        &IntErrorKind::PosOverflow | &IntErrorKind::NegOverflow => {
+            DovahkiinError::parse(format!("Number {} is out of range for {}", num_str, unit))
+        }
+        _ => DovahkiinError::parse(format!(
             "Cannot parse int {} with unit {}, reason: {:?}",
             num_str, unit, e
-        )
+        )),
     })
-    .map(SExpr::Value)
 }
 
-fn parse_float(num_str: String, unit: String) -> Result<SExpr, String> {
-    match unit.as_ref() {
-        "f32" => num_str.parse::<f32>().map(Value::F32),
-        "f64" => num_str.parse::<f64>().map(Value::F64),
-        _ => return Err(format!("Unknown float number type {}", unit)),
+pub(crate) fn parse_float(
+    num_str: String,
+    unit: String,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    if unit.is_empty() {
+        let unit = get_id_type(type_id_of(options.default_float_type));
+        return float_value(&num_str, unit).map(SExpr::Untyped);
+    }
+    float_value(&num_str, &unit).map(SExpr::Value)
+}
+
+pub(crate) fn float_value(num_str: &str, unit: &str) -> Result<Value, DovahkiinError> {
+    let digits: String = num_str.chars().filter(|&c| c != '_').collect();
+    let value = match unit {
+        "f32" => digits.parse::<f32>().map(Value::F32),
+        "f64" => digits.parse::<f64>().map(Value::F64),
+        _ => {
+            return Err(DovahkiinError::parse(format!(
+                "Unknown float number type {}",
+                unit
+            )))
+        }
     }
     .map_err(|e| {
-        format!(
+        DovahkiinError::parse(format!(
             "Cannot parse float {} with unit {}, reason: {:?}",
             num_str, unit, e
-        )
-    })
-    .map(SExpr::Value)
+        ))
+    })?;
+    match value {
+        // too large numbers are parsed as infinity
+        Value::F32(num) if num.is_infinite() => {}
+        Value::F64(num) if num.is_infinite() => {}
+        _ => return Ok(value),
+    }
+    Err(DovahkiinError::parse(format!(
+        "Number {} is out of range for {}",
+        num_str, unit
+    )))
 }
 
-fn parse_string(str: String) -> SExpr {
+pub(crate) fn parse_string(str: String) -> SExpr {
     SExpr::Value(Value::String(str))
 }
 
-fn parse_token(token: Token, iter: &mut IntoIter<Token>) -> Result<SExpr, String> {
+fn parse_token(
+    token: Token,
+    span: Span,
+    iter: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
     match token {
-        Token::LeftParentheses => Ok(parse_list(iter)?), // list
-        Token::Symbol(name) => Ok(parse_symbol(name)),
-        Token::IntNumber(num, unit) => Ok(parse_int(num, unit)?),
-        Token::FloatNumber(num, unit) => Ok(parse_float(num, unit)?),
+        Token::LeftParentheses => Ok(parse_list(span, iter, options)?), // list
+        Token::Symbol(name) => Ok(match name.as_ref() {
+            "true" => SExpr::Value(Value::Bool(true)),
+            "false" => SExpr::Value(Value::Bool(false)),
+            "nil" => SExpr::Value(Value::Null),
+            _ => parse_symbol(name),
+        }),
+        Token::Keyword(name) => Ok(parse_keyword(name)),
+        Token::IntNumber(num, unit) => {
+            Ok(parse_int(num, unit, options).map_err(|e| e.with_span(span))?)
+        }
+        Token::FloatNumber(num, unit) => {
+            Ok(parse_float(num, unit, options).map_err(|e| e.with_span(span))?)
+        }
         Token::String(str) => Ok(parse_string(str)),
-        Token::LeftVecParentheses => Ok(parse_vec(iter)?),
-        _ => Err(format!("Unexpected start token {}", token.to_string())),
+        Token::Char(c) => Ok(SExpr::Value(Value::Char(c))),
+        Token::Bytes(data) => Ok(SExpr::Value(Value::Bytes(Bytes::from_vec(data)))),
+        Token::LeftVecParentheses => Ok(parse_vec(span, iter, options)?),
+        Token::LeftMapParentheses => Ok(parse_map(span, iter, options)?),
+        Token::Tag(name) => Ok(parse_tagged(name, span, iter, options)?),
+        Token::Quote => Ok(parse_quote("quote", span, iter, options)?),
+        Token::QuasiQuote => Ok(parse_quote("quasiquote", span, iter, options)?),
+        Token::Unquote => Ok(parse_quote("unquote", span, iter, options)?),
+        Token::UnquoteSplicing => Ok(parse_quote("unquote-splicing", span, iter, options)?),
+        _ => Err(
+            DovahkiinError::parse(format!("Unexpected start token {}", token.to_string()))
+                .with_span(span),
+        ),
     }
 }
 
-pub fn parse_to_sexpr(tokens: Vec<Token>) -> Result<Vec<SExpr>, String> {
+pub fn parse_to_sexpr(tokens: Vec<SpannedToken>) -> Result<Vec<SExpr>, DovahkiinError> {
+    parse_to_sexpr_with_options(tokens, &ParserOptions::default())
+}
+
+pub fn parse_to_sexpr_with_options(
+    tokens: Vec<SpannedToken>,
+    options: &ParserOptions,
+) -> Result<Vec<SExpr>, DovahkiinError> {
     let mut exprs: Vec<SExpr> = Vec::new();
-    let mut iter = tokens.into_iter();
-    while let Some(token) = iter.next() {
-        exprs.push(parse_token(token, &mut iter)?)
+    let mut iter = tokens.into_iter().map(Ok);
+    while let Some(expr) = read_form(&mut iter, options)? {
+        exprs.push(expr)
     }
     Ok(exprs)
 }
+
+/// Reads the next form from tokens, None when there are no more forms
+pub fn read_form(
+    tokens: &mut Tokens,
+    options: &ParserOptions,
+) -> Result<Option<SExpr>, DovahkiinError> {
+    match next_token(tokens, options)? {
+        Some(SpannedToken { token, span }) => Ok(Some(parse_token(token, span, tokens, options)?)),
+        None => Ok(None),
+    }
+}
+
+/// Forms read one by one from a token stream like `lexer::lisp::TokenReader`.
+/// Stops after the first error.
+pub struct FormReader<I> {
+    tokens: I,
+    options: ParserOptions,
+    failed: bool,
+}
+
+impl<I> FormReader<I>
+where
+    I: Iterator<Item = Result<SpannedToken, DovahkiinError>>,
+{
+    pub fn new(tokens: I, options: ParserOptions) -> FormReader<I> {
+        FormReader {
+            tokens,
+            options,
+            failed: false,
+        }
+    }
+}
+
+impl<I> Iterator for FormReader<I>
+where
+    I: Iterator<Item = Result<SpannedToken, DovahkiinError>>,
+{
+    type Item = Result<SExpr, DovahkiinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match read_form(&mut self.tokens, &self.options) {
+            Ok(form) => form.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -1 +1,4 @@
+pub mod cst;
+pub mod json;
 pub mod lisp;
+pub mod sql;
@@ -0,0 +1,467 @@
+use error::DovahkiinError;
+use expr::SExpr;
+use lexer::sql::{tokenize_str, SpannedToken, Token};
+use parser::lisp::{
+    parse_float, parse_int, parse_keyword, parse_string, parse_symbol, ParserOptions,
+};
+use span::Span;
+use types::OwnedValue as Value;
+
+// A SELECT query is lowered to the stream functions over the rows of the source:
+//
+//   SELECT name, age + 1 AS next FROM people WHERE age > 18 ORDER BY age DESC LIMIT 10
+//
+//   (map (lambda [row] (hash-map "name" (:name row) "next" (+ (:age row) 1)))
+//     (take 10
+//       (sort-by (lambda [row] (:age row)) (filter (lambda [row] (> (:age row) 18)) people) :desc)))
+//
+// Names are columns of the row, `a.b` reads field b of the map in column a. WHERE and ORDER BY
+// see the source rows, not the selected columns. FROM takes a bound symbol or a subquery.
+// Result columns are named by their alias, the column or function name, or _n by position.
+
+const ROW: &str = "row";
+
+const KEYWORDS: [&str; 15] = [
+    "select", "from", "where", "order", "by", "asc", "desc", "limit", "as", "and", "or", "not",
+    "true", "false", "null",
+];
+
+fn list(span: Span, exprs: Vec<SExpr>) -> SExpr {
+    SExpr::Located(span, Box::new(SExpr::List(exprs)))
+}
+
+fn symbol(name: &str) -> SExpr {
+    parse_symbol(name.to_string())
+}
+
+// (lambda [row] body)
+fn row_lambda(body: SExpr) -> SExpr {
+    let span = body.span();
+    let lambda = SExpr::List(vec![symbol("lambda"), SExpr::Vec(vec![symbol(ROW)]), body]);
+    match span {
+        Some(span) => SExpr::Located(span, Box::new(lambda)),
+        None => lambda,
+    }
+}
+
+// symbols of operators, results of these have no name
+const OPERATORS: [&str; 12] = [
+    "+", "-", "*", "/", "=", "<", "<=", ">", ">=", "and", "or", "not",
+];
+
+// (op lhs rhs), a != b is lowered to (not (= a b))
+fn binary_form(span: Span, function: &str, lhs: SExpr, rhs: SExpr) -> SExpr {
+    match function {
+        "!=" => list(
+            span,
+            vec![symbol("not"), list(span, vec![symbol("="), lhs, rhs])],
+        ),
+        _ => list(span, vec![symbol(function), lhs, rhs]),
+    }
+}
+
+fn column_name(expr: &SExpr) -> Option<String> {
+    match expr.unlocated() {
+        // (:name row) or (name args...)
+        &SExpr::List(ref exprs) => match exprs.first().map(|head| head.unlocated()) {
+            Some(&SExpr::Keyword(_, ref name)) => Some(name.clone()),
+            Some(&SExpr::ISymbol(_, ref name)) if !OPERATORS.contains(&name.as_str()) => {
+                Some(name.clone())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct OrderBy {
+    key: SExpr,
+    descending: bool,
+}
+
+struct SqlParser {
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+    // where the input ends, for errors on missing tokens
+    end: Span,
+    options: ParserOptions,
+}
+
+impl SqlParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|spanned| &spanned.token)
+    }
+
+    fn span(&self) -> Span {
+        self.tokens
+            .get(self.pos)
+            .map(|spanned| spanned.span)
+            .unwrap_or(self.end)
+    }
+
+    // span from the token at `start` to the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        let first = self.tokens[start].span;
+        first.to(&self.tokens[self.pos.max(start + 1) - 1].span)
+    }
+
+    fn advance(&mut self) -> Option<SpannedToken> {
+        let spanned = self.tokens.get(self.pos).map(|spanned| SpannedToken {
+            token: spanned.token.clone(),
+            span: spanned.span,
+        });
+        self.pos += 1;
+        spanned
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Ident(ref name)) => name.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let is_keyword = self.is_keyword(keyword);
+        if is_keyword {
+            self.pos += 1;
+        }
+        is_keyword
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self, expected: &str) -> DovahkiinError {
+        match self.peek() {
+            Some(token) => {
+                DovahkiinError::parse(format!("Expect {}, found {}", expected, token.to_string()))
+            }
+            None => DovahkiinError::incomplete(format!("Unexpected EOF, expect {}", expected)),
+        }
+        .with_span(self.span())
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), DovahkiinError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&keyword.to_uppercase()))
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), DovahkiinError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", token.to_string())))
+        }
+    }
+
+    // names which are not keywords, or quoted
+    fn name(&mut self) -> Option<String> {
+        let name = match self.peek() {
+            Some(&Token::Ident(ref name)) if !KEYWORDS.contains(&name.to_lowercase().as_str()) => {
+                name.clone()
+            }
+            Some(&Token::QuotedIdent(ref name)) => name.clone(),
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(name)
+    }
+
+    fn expect_name(&mut self) -> Result<String, DovahkiinError> {
+        match self.name() {
+            Some(name) => Ok(name),
+            None => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn query(&mut self) -> Result<SExpr, DovahkiinError> {
+        let start = self.pos;
+        self.expect_keyword("select")?;
+        let columns = if self.eat(&Token::Star) {
+            None
+        } else {
+            let mut columns = Vec::new();
+            loop {
+                let expr = self.expr()?;
+                let name = if self.eat_keyword("as") {
+                    self.expect_name()?
+                } else {
+                    match self.name() {
+                        Some(name) => name,
+                        None => {
+                            column_name(&expr).unwrap_or_else(|| format!("_{}", columns.len() + 1))
+                        }
+                    }
+                };
+                columns.push((name, expr));
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+            Some(columns)
+        };
+        self.expect_keyword("from")?;
+        let mut rows = if self.eat(&Token::LeftParentheses) {
+            let subquery = self.query()?;
+            self.expect(Token::RightParentheses)?;
+            subquery
+        } else {
+            symbol(&self.expect_name()?)
+        };
+        if self.eat_keyword("where") {
+            let condition = self.expr()?;
+            rows = list(
+                self.span_from(start),
+                vec![symbol("filter"), row_lambda(condition), rows],
+            );
+        }
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            let mut order = Vec::new();
+            loop {
+                let key = self.expr()?;
+                let descending = if self.eat_keyword("desc") {
+                    true
+                } else {
+                    self.eat_keyword("asc");
+                    false
+                };
+                order.push(OrderBy { key, descending });
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+            // stable sorts from the last key, so earlier keys take precedence
+            for OrderBy { key, descending } in order.into_iter().rev() {
+                let mut sort = vec![symbol("sort-by"), row_lambda(key), rows];
+                if descending {
+                    sort.push(parse_keyword("desc".to_string()));
+                }
+                rows = list(self.span_from(start), sort);
+            }
+        }
+        if self.eat_keyword("limit") {
+            let limit = self.expr()?;
+            rows = list(self.span_from(start), vec![symbol("take"), limit, rows]);
+        }
+        if let Some(columns) = columns {
+            let mut map = vec![symbol("hash-map")];
+            for (name, expr) in columns {
+                map.push(parse_string(name));
+                map.push(expr);
+            }
+            rows = list(
+                self.span_from(start),
+                vec![symbol("map"), row_lambda(SExpr::List(map)), rows],
+            );
+        }
+        Ok(rows)
+    }
+
+    fn binary<F>(
+        &mut self,
+        operand: F,
+        operators: &[(Token, &str)],
+    ) -> Result<SExpr, DovahkiinError>
+    where
+        F: Fn(&mut SqlParser) -> Result<SExpr, DovahkiinError>,
+    {
+        let start = self.pos;
+        let mut lhs = operand(self)?;
+        'operators: loop {
+            for &(ref token, function) in operators {
+                if self.eat(token) {
+                    let rhs = operand(self)?;
+                    lhs = binary_form(self.span_from(start), function, lhs, rhs);
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    // and, or with any number of operands
+    fn logic(
+        &mut self,
+        keyword: &str,
+        operand: fn(&mut SqlParser) -> Result<SExpr, DovahkiinError>,
+    ) -> Result<SExpr, DovahkiinError> {
+        let start = self.pos;
+        let mut operands = vec![operand(self)?];
+        while self.eat_keyword(keyword) {
+            operands.push(operand(self)?);
+        }
+        if operands.len() == 1 {
+            return Ok(operands.pop().unwrap());
+        }
+        operands.insert(0, symbol(keyword));
+        Ok(list(self.span_from(start), operands))
+    }
+
+    fn expr(&mut self) -> Result<SExpr, DovahkiinError> {
+        self.logic("or", SqlParser::and_expr)
+    }
+
+    fn and_expr(&mut self) -> Result<SExpr, DovahkiinError> {
+        self.logic("and", SqlParser::not_expr)
+    }
+
+    fn not_expr(&mut self) -> Result<SExpr, DovahkiinError> {
+        let start = self.pos;
+        if self.eat_keyword("not") {
+            let operand = self.not_expr()?;
+            return Ok(list(self.span_from(start), vec![symbol("not"), operand]));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<SExpr, DovahkiinError> {
+        self.binary(
+            SqlParser::additive,
+            &[
+                (Token::Equals, "="),
+                (Token::NotEquals, "!="),
+                (Token::LessThan, "<"),
+                (Token::LessThanEquals, "<="),
+                (Token::GreaterThan, ">"),
+                (Token::GreaterThanEquals, ">="),
+            ],
+        )
+    }
+
+    fn additive(&mut self) -> Result<SExpr, DovahkiinError> {
+        self.binary(
+            SqlParser::multiplicative,
+            &[(Token::Plus, "+"), (Token::Minus, "-")],
+        )
+    }
+
+    fn multiplicative(&mut self) -> Result<SExpr, DovahkiinError> {
+        self.binary(SqlParser::unary, &[(Token::Star, "*"), (Token::Slash, "/")])
+    }
+
+    fn unary(&mut self) -> Result<SExpr, DovahkiinError> {
+        let start = self.pos;
+        if !self.eat(&Token::Minus) {
+            return self.primary();
+        }
+        match self.peek() {
+            // negative literals keep their type like in lisp
+            Some(&Token::IntNumber(..)) | Some(&Token::FloatNumber(..)) => self.number("-"),
+            // the -1 takes the type of the operand, negating an unsigned one is a type error
+            _ => {
+                let operand = self.unary()?;
+                Ok(list(
+                    self.span_from(start),
+                    vec![symbol("*"), SExpr::Untyped(Value::I64(-1)), operand],
+                ))
+            }
+        }
+    }
+
+    fn number(&mut self, sign: &str) -> Result<SExpr, DovahkiinError> {
+        let SpannedToken { token, span } = self.advance().unwrap();
+        match token {
+            Token::IntNumber(num, unit) => {
+                parse_int(format!("{}{}", sign, num), unit, &self.options)
+            }
+            Token::FloatNumber(num, unit) => {
+                parse_float(format!("{}{}", sign, num), unit, &self.options)
+            }
+            _ => unreachable!(),
+        }
+        .map_err(|e| e.with_span(span))
+    }
+
+    fn primary(&mut self) -> Result<SExpr, DovahkiinError> {
+        let start = self.pos;
+        match self.peek() {
+            Some(&Token::IntNumber(..)) | Some(&Token::FloatNumber(..)) => return self.number(""),
+            Some(&Token::String(ref str)) => {
+                let str = str.clone();
+                self.pos += 1;
+                return Ok(parse_string(str));
+            }
+            Some(&Token::LeftParentheses) => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(Token::RightParentheses)?;
+                return Ok(expr);
+            }
+            _ => {}
+        }
+        for &(keyword, ref value) in &[
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Null),
+        ] {
+            if self.eat_keyword(keyword) {
+                return Ok(SExpr::Value(value.clone()));
+            }
+        }
+        let name = match self.name() {
+            Some(name) => name,
+            None => return Err(self.unexpected("an expression")),
+        };
+        if self.eat(&Token::LeftParentheses) {
+            let mut call = vec![symbol(&name)];
+            if !self.eat(&Token::RightParentheses) {
+                loop {
+                    call.push(self.expr()?);
+                    if !self.eat(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.expect(Token::RightParentheses)?;
+            }
+            return Ok(list(self.span_from(start), call));
+        }
+        // a.b.c is read as (:c (:b (:a row)))
+        let mut column = list(
+            self.span_from(start),
+            vec![parse_keyword(name), symbol(ROW)],
+        );
+        while self.eat(&Token::Dot) {
+            let field = self.expect_name()?;
+            column = list(self.span_from(start), vec![parse_keyword(field), column]);
+        }
+        Ok(column)
+    }
+}
+
+/// Lowers a SELECT query to the expression reading the rows, see the form above
+pub fn parse_to_sexpr(code: &str) -> Result<SExpr, DovahkiinError> {
+    parse_to_sexpr_with_options(code, &ParserOptions::default())
+}
+
+pub fn parse_to_sexpr_with_options(
+    code: &str,
+    options: &ParserOptions,
+) -> Result<SExpr, DovahkiinError> {
+    let tokens = tokenize_str(code)?;
+    let end = match tokens.last() {
+        Some(spanned) => Span::at(spanned.span.end),
+        None => Span::at(::span::Position::start()),
+    };
+    let mut parser = SqlParser {
+        tokens,
+        pos: 0,
+        end,
+        options: *options,
+    };
+    let query = parser.query()?;
+    parser.eat(&Token::Semicolon);
+    if parser.peek().is_some() {
+        return Err(parser.unexpected("end of query"));
+    }
+    Ok(query)
+}
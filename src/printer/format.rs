@@ -0,0 +1,247 @@
+use error::DovahkiinError;
+use lexer::lisp::Token;
+use parser::cst::{parse_cst, Node};
+use printer::lisp::{header_size, DEFAULT_WIDTH};
+
+// A form or comment with the number of line breaks before it in the source
+struct Item<'a> {
+    node: &'a Node,
+    newlines_before: usize,
+}
+
+fn items<'a>(nodes: &'a [Node]) -> Vec<Item<'a>> {
+    let mut items = Vec::new();
+    let mut newlines = 0;
+    for node in nodes {
+        match node {
+            &Node::Whitespace(ref text) => newlines += text.matches('\n').count(),
+            _ => {
+                items.push(Item {
+                    node,
+                    newlines_before: newlines,
+                });
+                newlines = 0;
+            }
+        }
+    }
+    items
+}
+
+fn is_line_comment(node: &Node) -> bool {
+    match node {
+        &Node::Comment(ref leaf) => match leaf.token {
+            Token::LineComment(_) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn leaf_text(node: &Node) -> Option<&str> {
+    match node {
+        // line comments stop before '\n', a '\r' can be left
+        &Node::Comment(ref leaf) => Some(leaf.text.trim_end()),
+        &Node::Atom(ref leaf) => Some(&leaf.text),
+        _ => None,
+    }
+}
+
+// '~' needs a space before a form starting with '@', or it would be read as '~@'
+fn prefix_separator(prefix: &Token, form: &str) -> &'static str {
+    match prefix {
+        &Token::DatumComment | &Token::Tag(_) => " ",
+        &Token::Unquote if form.starts_with('@') => " ",
+        _ => "",
+    }
+}
+
+// The node on one line, None when it has to be broken like with line comments inside
+fn flat(node: &Node) -> Option<String> {
+    if let Some(text) = leaf_text(node) {
+        return if is_line_comment(node) || text.contains('\n') {
+            None
+        } else {
+            Some(text.to_string())
+        };
+    }
+    match node {
+        &Node::Group {
+            ref open,
+            ref children,
+            ref close,
+        } => {
+            let mut parts = Vec::new();
+            for item in items(children) {
+                parts.push(flat(item.node)?);
+            }
+            Some(format!("{}{}{}", open.text, parts.join(" "), close.text))
+        }
+        &Node::Prefixed {
+            ref prefix,
+            ref children,
+        } => {
+            let mut parts = Vec::new();
+            for item in items(children) {
+                parts.push(flat(item.node)?);
+            }
+            let rest = parts.join(" ");
+            Some(format!(
+                "{}{}{}",
+                prefix.text,
+                prefix_separator(&prefix.token, &rest),
+                rest
+            ))
+        }
+        _ => None,
+    }
+}
+
+struct Formatter {
+    out: String,
+    column: usize,
+    width: usize,
+}
+
+impl Formatter {
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+        match text.rfind('\n') {
+            Some(pos) => self.column = text[pos + 1..].chars().count(),
+            None => self.column += text.chars().count(),
+        }
+    }
+
+    fn new_line(&mut self, indent: usize, blank_line: bool) {
+        if blank_line {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        self.out.extend(::std::iter::repeat(' ').take(indent));
+        self.column = indent;
+    }
+
+    fn format(&mut self, node: &Node) {
+        if let Some(text) = leaf_text(node) {
+            return self.write(text);
+        }
+        if let Some(text) = flat(node) {
+            if self.column + text.chars().count() <= self.width {
+                return self.write(&text);
+            }
+        }
+        match node {
+            &Node::Group {
+                ref open,
+                ref children,
+                ref close,
+            } => {
+                let start = self.column;
+                let items = items(children);
+                let (indent, inline, is_map) = match open.token {
+                    Token::LeftParentheses => {
+                        let header = match items.first().and_then(|item| leaf_text(item.node)) {
+                            Some(head) => header_size(head),
+                            None => 0,
+                        };
+                        (start + 2, header + 1, false)
+                    }
+                    // key and value pairs stay on one line
+                    Token::LeftMapParentheses => (start + 1, 2, true),
+                    _ => (start + 1, 1, false),
+                };
+                self.write(&open.text);
+                self.format_items(&items, indent, inline, is_map);
+                self.write(&close.text);
+            }
+            &Node::Prefixed {
+                ref prefix,
+                ref children,
+            } => {
+                let start = self.column;
+                self.write(&prefix.text);
+                let items = items(children);
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 && is_line_comment(items[i - 1].node) {
+                        self.new_line(start, false);
+                    } else {
+                        let first = flat(item.node).unwrap_or_default();
+                        self.write(if i > 0 || is_line_comment(item.node) {
+                            " "
+                        } else {
+                            prefix_separator(&prefix.token, &first)
+                        });
+                    }
+                    self.format(item.node);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // the first `inline` forms stay on the opening line, others are put on lines at `indent`
+    fn format_items(&mut self, items: &[Item], indent: usize, inline: usize, is_map: bool) {
+        let mut forms = 0;
+        let mut after_line_comment = false;
+        let mut previous_inline = true;
+        for (i, item) in items.iter().enumerate() {
+            let is_comment = item.node.is_trivia();
+            let stays_on_line = if i == 0 {
+                true
+            } else if after_line_comment {
+                false
+            } else if is_comment {
+                // trailing comments stay after the form they were written after
+                item.newlines_before == 0
+            } else {
+                (previous_inline && forms < inline) || (is_map && forms % 2 == 1)
+            };
+            if i > 0 {
+                if stays_on_line {
+                    self.write(" ");
+                } else {
+                    self.new_line(indent, item.newlines_before > 1);
+                }
+            }
+            self.format(item.node);
+            if !is_comment {
+                forms += 1;
+            }
+            previous_inline = previous_inline && stays_on_line;
+            after_line_comment = is_line_comment(item.node);
+        }
+        if after_line_comment {
+            self.new_line(indent, false);
+        }
+    }
+}
+
+/// Formats lisp source in the standard layout. Comments and the spelling of literals are kept,
+/// forms that do not fit in the width are broken into indented lines.
+pub fn format_source(code: &str) -> Result<String, DovahkiinError> {
+    format_source_with_width(code, DEFAULT_WIDTH)
+}
+
+pub fn format_source_with_width(code: &str, width: usize) -> Result<String, DovahkiinError> {
+    let nodes = parse_cst(code)?;
+    let mut formatter = Formatter {
+        out: String::new(),
+        column: 0,
+        width,
+    };
+    let items = items(&nodes);
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            if item.newlines_before == 0 && item.node.is_trivia() {
+                formatter.write(" ");
+            } else {
+                // keep one blank line between forms where there was any
+                formatter.new_line(0, item.newlines_before > 1);
+            }
+        }
+        formatter.format(item.node);
+    }
+    if !items.is_empty() {
+        formatter.out.push('\n');
+    }
+    Ok(formatter.out)
+}
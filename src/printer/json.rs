@@ -0,0 +1,210 @@
+use error::DovahkiinError;
+use expr::SExpr;
+use parser::json::{binding_position, KEYWORD_KEY, MAP_KEY, VEC_KEY};
+use serde_json::{Map, Number, Value as Json};
+use types::Bytes;
+use types::OwnedValue as Value;
+
+// Exports expressions in the JSON form read by `parser::json`, see the format there
+
+fn object(key: &str, json: Json) -> Json {
+    let mut entries = Map::new();
+    entries.insert(key.to_string(), json);
+    Json::Object(entries)
+}
+
+fn float_json(num: f64) -> Result<Json, DovahkiinError> {
+    Number::from_f64(num)
+        .map(Json::Number)
+        .ok_or_else(|| DovahkiinError::type_error(format!("Float {} has no JSON form", num)))
+}
+
+// f32 is widened through its shortest decimal, so 0.1f32 is exported as 0.1
+fn float32_json(num: f32) -> Result<Json, DovahkiinError> {
+    float_json(num.to_string().parse().unwrap_or(num as f64))
+}
+
+fn name(expr: &SExpr) -> Option<&str> {
+    match expr.unlocated() {
+        &SExpr::Symbol(ref name) | &SExpr::ISymbol(_, ref name) => Some(name),
+        _ => None,
+    }
+}
+
+fn exprs_json(exprs: &[SExpr]) -> Result<Vec<Json>, DovahkiinError> {
+    exprs.iter().map(expr_to_json).collect()
+}
+
+// {"$map": {...}} when all keys are keywords or strings which do not read as keywords
+fn map_json(entries: &[SExpr]) -> Result<Option<Json>, DovahkiinError> {
+    if entries.len() % 2 != 0 {
+        return Ok(None);
+    }
+    let mut map = Map::new();
+    for pair in entries.chunks(2) {
+        let key = match pair[0].unlocated() {
+            &SExpr::Keyword(_, ref name) => format!(":{}", name),
+            &SExpr::Value(Value::String(ref key)) if !key.starts_with(':') => key.clone(),
+            _ => return Ok(None),
+        };
+        if map.contains_key(&key) {
+            return Ok(None);
+        }
+        map.insert(key, expr_to_json(&pair[1])?);
+    }
+    Ok(Some(object(MAP_KEY, Json::Object(map))))
+}
+
+fn list_json(exprs: &[SExpr]) -> Result<Json, DovahkiinError> {
+    let head = match exprs.first().and_then(name) {
+        Some(head) => head,
+        None => return Ok(Json::Array(exprs_json(exprs)?)),
+    };
+    let args = &exprs[1..];
+    if head == "hash-map" {
+        if let Some(json) = map_json(args)? {
+            return Ok(json);
+        }
+    }
+    if let Some(pos) = binding_position(head) {
+        if let Some(&SExpr::Vec(ref bindings)) = args.get(pos).map(|arg| arg.unlocated()) {
+            let mut args_json = exprs_json(args)?;
+            args_json[pos] = Json::Array(exprs_json(bindings)?);
+            return Ok(object(head, Json::Array(args_json)));
+        }
+    }
+    Ok(Json::Array(exprs_json(exprs)?))
+}
+
+fn untyped_json(value: &Value) -> Result<Json, DovahkiinError> {
+    Ok(match value {
+        &Value::F32(num) => return float32_json(num),
+        &Value::F64(num) => return float_json(num),
+        &Value::I8(num) => Json::from(num),
+        &Value::I16(num) => Json::from(num),
+        &Value::I32(num) => Json::from(num),
+        &Value::I64(num) => Json::from(num),
+        &Value::U8(num) => Json::from(num),
+        &Value::U16(num) => Json::from(num),
+        &Value::U32(num) => Json::from(num),
+        &Value::U64(num) => Json::from(num),
+        _ => return value_to_json(value),
+    })
+}
+
+/// JSON form which `parser::json` reads back to the same expression, except for source
+/// locations. Compiled lambdas are exported as lambda forms, without the bindings they captured.
+pub fn expr_to_json(expr: &SExpr) -> Result<Json, DovahkiinError> {
+    match expr {
+        &SExpr::Symbol(ref name) | &SExpr::ISymbol(_, ref name) => Ok(Json::String(name.clone())),
+        &SExpr::Keyword(_, ref name) => Ok(object(KEYWORD_KEY, Json::String(name.clone()))),
+        &SExpr::Value(ref value) => value_to_json(value),
+        &SExpr::Untyped(ref value) => untyped_json(value),
+        &SExpr::List(ref exprs) => list_json(exprs),
+        &SExpr::Vec(ref exprs) => Ok(object(VEC_KEY, Json::Array(exprs_json(exprs)?))),
+        &SExpr::LAMBDA(ref params, ref body, _) => {
+            let mut args = vec![Json::Array(exprs_json(params)?)];
+            args.extend(exprs_json(body)?);
+            Ok(object("lambda", Json::Array(args)))
+        }
+        &SExpr::Located(_, ref expr) => expr_to_json(expr),
+    }
+}
+
+/// Typed JSON form of the value, which evaluates back to the value
+pub fn value_to_json(value: &Value) -> Result<Json, DovahkiinError> {
+    let (key, json) = match value {
+        &Value::Null => return Ok(Json::Null),
+        &Value::Bool(b) => return Ok(Json::Bool(b)),
+        &Value::Char(c) => ("char", Json::String(c.to_string())),
+        &Value::I8(num) => ("i8", Json::from(num)),
+        &Value::I16(num) => ("i16", Json::from(num)),
+        &Value::I32(num) => ("i32", Json::from(num)),
+        &Value::I64(num) => ("i64", Json::from(num)),
+        &Value::U8(num) => ("u8", Json::from(num)),
+        &Value::U16(num) => ("u16", Json::from(num)),
+        &Value::U32(num) => ("u32", Json::from(num)),
+        &Value::U64(num) => ("u64", Json::from(num)),
+        &Value::F32(num) => ("f32", float32_json(num)?),
+        &Value::F64(num) => ("f64", float_json(num)?),
+        &Value::String(ref str) => ("string", Json::String(str.clone())),
+        &Value::Bytes(ref bytes) => ("bytes", Json::String(bytes.to_base64())),
+        // read back as bytes, like in lisp
+        &Value::SmallBytes(ref bytes) => (
+            "bytes",
+            Json::String(Bytes::from_vec(bytes.data.clone()).to_base64()),
+        ),
+        &Value::Id(ref id) => (
+            "id",
+            Json::String(format!("{:016x}-{:016x}", id.higher, id.lower)),
+        ),
+        &Value::Pos2d32(ref pos) => (
+            "pos2d32",
+            Json::Array(vec![float32_json(pos.x)?, float32_json(pos.y)?]),
+        ),
+        &Value::Pos2d64(ref pos) => (
+            "pos2d64",
+            Json::Array(vec![float_json(pos.x)?, float_json(pos.y)?]),
+        ),
+        &Value::Pos3d32(ref pos) => (
+            "pos3d32",
+            Json::Array(vec![
+                float32_json(pos.x)?,
+                float32_json(pos.y)?,
+                float32_json(pos.z)?,
+            ]),
+        ),
+        &Value::Pos3d64(ref pos) => (
+            "pos3d64",
+            Json::Array(vec![
+                float_json(pos.x)?,
+                float_json(pos.y)?,
+                float_json(pos.z)?,
+            ]),
+        ),
+        &Value::Array(ref array) => (
+            VEC_KEY,
+            Json::Array(array.iter().map(value_to_json).collect::<Result<_, _>>()?),
+        ),
+        &Value::PrimArray(_) => (
+            VEC_KEY,
+            Json::Array(
+                value
+                    .cloned_iter_value()
+                    .unwrap()
+                    .map(|v| value_to_json(&v))
+                    .collect::<Result<_, _>>()?,
+            ),
+        ),
+        &Value::Map(ref map) => {
+            // entries only set by key id have no name to export
+            let mut entries = Map::new();
+            for field in &map.fields {
+                if !entries.contains_key(field) {
+                    entries.insert(field.clone(), value_to_json(map.get(field))?);
+                }
+            }
+            // names starting with ':' would read back as keywords, build those with hash-map
+            if entries.keys().any(|field| field.starts_with(':')) {
+                let mut items = vec![Json::String("hash-map".to_string())];
+                for (field, json) in entries {
+                    items.push(object("string", Json::String(field)));
+                    items.push(json);
+                }
+                return Ok(Json::Array(items));
+            }
+            (MAP_KEY, Json::Object(entries))
+        }
+        &Value::NA => {
+            return Err(DovahkiinError::type_error(
+                "NA has no JSON form".to_string(),
+            ))
+        }
+    };
+    Ok(object(key, json))
+}
+
+/// JSON text of the expression
+pub fn print_expr(expr: &SExpr) -> Result<String, DovahkiinError> {
+    Ok(expr_to_json(expr)?.to_string())
+}
@@ -0,0 +1,367 @@
+use error::DovahkiinError;
+use expr::SExpr;
+use lexer::lisp::Token;
+use std::collections::HashSet;
+use types::key_hash;
+use types::OwnedValue as Value;
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+// Layout tree of the code to print. Groups are printed on one line when they fit,
+// otherwise their items are broken into lines.
+enum Doc {
+    Atom(String),
+    Group {
+        open: &'static str,
+        close: &'static str,
+        items: Vec<Doc>,
+        style: Style,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    // (head args...), `header` args are kept on the line of the head, others are indented by 2
+    List { header: usize },
+    // [items...], aligned after the bracket
+    Vec,
+    // {k v ...}, one pair per line
+    Map,
+}
+
+impl Doc {
+    fn flat_width(&self) -> usize {
+        match self {
+            &Doc::Atom(ref str) => str.chars().count(),
+            &Doc::Group {
+                open,
+                close,
+                ref items,
+                ..
+            } => {
+                let items_width: usize = items.iter().map(|item| item.flat_width()).sum();
+                open.len() + close.len() + items_width + items.len().saturating_sub(1)
+            }
+        }
+    }
+}
+
+// forms with their first arguments kept on the line of the head, like (defunc name [params]
+pub(crate) fn header_size(head: &str) -> usize {
+    match head {
+        "defunc" => 2,
+        "def" | "let" | "lambda" | "if" | "if-not" | "when" | "when-not" | "map" | "filter"
+        | "catch" => 1,
+        _ => 0,
+    }
+}
+
+fn list_doc(items: Vec<Doc>) -> Doc {
+    let header = match items.first() {
+        Some(&Doc::Atom(ref head)) => header_size(head),
+        _ => 0,
+    };
+    Doc::Group {
+        open: "(",
+        close: ")",
+        items,
+        style: Style::List { header },
+    }
+}
+
+fn vec_doc(items: Vec<Doc>) -> Doc {
+    Doc::Group {
+        open: "[",
+        close: "]",
+        items,
+        style: Style::Vec,
+    }
+}
+
+fn float_str(num: f64) -> String {
+    // debug format always has a '.' or an exponent, so the number is read back as float.
+    // NaN and infinities have no literal.
+    format!("{:?}", num)
+}
+
+fn float32_str(num: f32) -> String {
+    format!("{:?}", num)
+}
+
+fn expr_doc(expr: &SExpr) -> Doc {
+    match expr {
+        &SExpr::Symbol(ref name) | &SExpr::ISymbol(_, ref name) => Doc::Atom(name.clone()),
+        &SExpr::Keyword(_, ref name) => Doc::Atom(format!(":{}", name)),
+        &SExpr::Value(ref value) => value_doc(value),
+        &SExpr::Untyped(ref value) => Doc::Atom(match value {
+            &Value::F32(num) => float32_str(num),
+            &Value::F64(num) => float_str(num),
+            &Value::I8(num) => num.to_string(),
+            &Value::I16(num) => num.to_string(),
+            &Value::I32(num) => num.to_string(),
+            &Value::I64(num) => num.to_string(),
+            &Value::U8(num) => num.to_string(),
+            &Value::U16(num) => num.to_string(),
+            &Value::U32(num) => num.to_string(),
+            &Value::U64(num) => num.to_string(),
+            _ => return value_doc(value),
+        }),
+        &SExpr::List(ref exprs) => list_doc(exprs.iter().map(expr_doc).collect()),
+        &SExpr::Vec(ref exprs) => vec_doc(exprs.iter().map(expr_doc).collect()),
+        &SExpr::LAMBDA(ref params, ref body, _) => {
+            let mut items = vec![
+                Doc::Atom("lambda".to_string()),
+                vec_doc(params.iter().map(expr_doc).collect()),
+            ];
+            items.extend(body.iter().map(expr_doc));
+            list_doc(items)
+        }
+        &SExpr::Located(_, ref expr) => expr_doc(expr),
+    }
+}
+
+fn value_doc(value: &Value) -> Doc {
+    let atom = match value {
+        &Value::Null => "nil".to_string(),
+        &Value::Bool(b) => b.to_string(),
+        &Value::Char(c) => Token::Char(c).to_string(),
+        &Value::I8(num) => format!("{}i8", num),
+        &Value::I16(num) => format!("{}i16", num),
+        &Value::I32(num) => format!("{}i32", num),
+        &Value::I64(num) => format!("{}i64", num),
+        &Value::U8(num) => format!("{}u8", num),
+        &Value::U16(num) => format!("{}u16", num),
+        &Value::U32(num) => format!("{}u32", num),
+        &Value::U64(num) => format!("{}u64", num),
+        &Value::F32(num) => format!("{}f32", float32_str(num)),
+        &Value::F64(num) => format!("{}f64", float_str(num)),
+        &Value::String(ref str) => Token::String(str.clone()).to_string(),
+        &Value::Bytes(ref bytes) => Token::Bytes(bytes.data.clone()).to_string(),
+        // there is no literal for small bytes, they are read back as bytes
+        &Value::SmallBytes(ref bytes) => Token::Bytes(bytes.data.clone()).to_string(),
+        &Value::Id(ref id) => format!("#id \"{:016x}-{:016x}\"", id.higher, id.lower),
+        &Value::Pos2d32(ref pos) => format!(
+            "#pos2d32 [{}f32 {}f32]",
+            float32_str(pos.x),
+            float32_str(pos.y)
+        ),
+        &Value::Pos2d64(ref pos) => {
+            format!("#pos2d64 [{}f64 {}f64]", float_str(pos.x), float_str(pos.y))
+        }
+        &Value::Pos3d32(ref pos) => format!(
+            "#pos3d32 [{}f32 {}f32 {}f32]",
+            float32_str(pos.x),
+            float32_str(pos.y),
+            float32_str(pos.z)
+        ),
+        &Value::Pos3d64(ref pos) => format!(
+            "#pos3d64 [{}f64 {}f64 {}f64]",
+            float_str(pos.x),
+            float_str(pos.y),
+            float_str(pos.z)
+        ),
+        &Value::Array(ref array) => return vec_doc(array.iter().map(value_doc).collect()),
+        &Value::PrimArray(_) => {
+            return vec_doc(
+                value
+                    .cloned_iter_value()
+                    .unwrap()
+                    .map(|v| value_doc(&v))
+                    .collect(),
+            )
+        }
+        &Value::Map(ref map) => {
+            // entries are printed in field order. Entries only set by key id have no name to print.
+            let mut items = Vec::new();
+            let mut printed = Vec::new();
+            for field in &map.fields {
+                if printed.contains(&field) {
+                    continue;
+                }
+                printed.push(field);
+                items.push(Doc::Atom(Token::String(field.clone()).to_string()));
+                items.push(value_doc(map.get(field)));
+            }
+            return Doc::Group {
+                open: "{",
+                close: "}",
+                items,
+                style: Style::Map,
+            };
+        }
+        // not readable, there is no literal for NA
+        &Value::NA => "NA".to_string(),
+    };
+    Doc::Atom(atom)
+}
+
+struct Printer {
+    out: String,
+    column: usize,
+    width: usize,
+}
+
+impl Printer {
+    fn write(&mut self, str: &str) {
+        self.out.push_str(str);
+        self.column += str.chars().count();
+    }
+
+    fn new_line(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(::std::iter::repeat(' ').take(indent));
+        self.column = indent;
+    }
+
+    fn print(&mut self, doc: &Doc) {
+        match doc {
+            &Doc::Atom(ref str) => self.write(str),
+            &Doc::Group {
+                open,
+                close,
+                ref items,
+                style,
+            } => {
+                let start = self.column;
+                self.write(open);
+                if start + doc.flat_width() <= self.width {
+                    self.print_items(items.iter(), None);
+                } else {
+                    match style {
+                        Style::List { header } => {
+                            let inline = items.len().min(header + 1);
+                            self.print_items(items[..inline].iter(), None);
+                            for item in &items[inline..] {
+                                self.new_line(start + 2);
+                                self.print(item);
+                            }
+                        }
+                        Style::Vec => self.print_items(items.iter(), Some(start + 1)),
+                        Style::Map => {
+                            for (i, pair) in items.chunks(2).enumerate() {
+                                if i > 0 {
+                                    self.new_line(start + 1);
+                                }
+                                self.print_items(pair.iter(), None);
+                            }
+                        }
+                    }
+                }
+                self.write(close);
+            }
+        }
+    }
+
+    // items separated by a space, or by a new line at `break_indent`
+    fn print_items<'a, I>(&mut self, items: I, break_indent: Option<usize>)
+    where
+        I: Iterator<Item = &'a Doc>,
+    {
+        for (i, item) in items.enumerate() {
+            if i > 0 {
+                match break_indent {
+                    Some(indent) => self.new_line(indent),
+                    None => self.write(" "),
+                }
+            }
+            self.print(item);
+        }
+    }
+}
+
+fn print_doc(doc: Doc, width: usize) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        column: 0,
+        width,
+    };
+    printer.print(&doc);
+    printer.out
+}
+
+/// Prints the expression as source. `parse_to_expr` reads forms back to the same expression,
+/// except for source locations. Compiled lambdas are printed as lambda forms, without the
+/// bindings they captured. Values are printed as literals, see `print_value`, so arrays
+/// and maps are read back as vector and map forms instead of values.
+pub fn print_expr(expr: &SExpr) -> String {
+    print_expr_with_width(expr, DEFAULT_WIDTH)
+}
+
+fn float_unreadable(nums: &[f64]) -> Option<String> {
+    nums.iter()
+        .find(|num| !num.is_finite())
+        .map(|num| format!("Float {} has no literal", num))
+}
+
+// Why the value is not printed as a literal evaluating back to it, None when it is
+fn unreadable_value(value: &Value) -> Option<String> {
+    match value {
+        &Value::F32(num) => float_unreadable(&[num as f64]),
+        &Value::F64(num) => float_unreadable(&[num]),
+        &Value::Pos2d32(ref pos) => float_unreadable(&[pos.x as f64, pos.y as f64]),
+        &Value::Pos2d64(ref pos) => float_unreadable(&[pos.x, pos.y]),
+        &Value::Pos3d32(ref pos) => float_unreadable(&[pos.x as f64, pos.y as f64, pos.z as f64]),
+        &Value::Pos3d64(ref pos) => float_unreadable(&[pos.x, pos.y, pos.z]),
+        &Value::Array(ref array) => array.iter().filter_map(unreadable_value).next(),
+        &Value::PrimArray(_) => value
+            .cloned_iter_value()
+            .unwrap()
+            .filter_map(|v| unreadable_value(&v))
+            .next(),
+        &Value::Map(ref map) => {
+            let named: HashSet<u64> = map.fields.iter().map(|field| key_hash(field)).collect();
+            if map.map.keys().any(|key_id| !named.contains(key_id)) {
+                return Some("Map entries only set by key id have no name to print".to_string());
+            }
+            map.map.values().filter_map(unreadable_value).next()
+        }
+        &Value::NA => Some("NA has no literal".to_string()),
+        _ => None,
+    }
+}
+
+fn unreadable_expr(expr: &SExpr) -> Option<String> {
+    match expr {
+        &SExpr::Value(ref value) | &SExpr::Untyped(ref value) => unreadable_value(value),
+        &SExpr::List(ref exprs) | &SExpr::Vec(ref exprs) => {
+            exprs.iter().filter_map(unreadable_expr).next()
+        }
+        &SExpr::LAMBDA(ref params, ref body, _) => params
+            .iter()
+            .chain(body.iter())
+            .filter_map(unreadable_expr)
+            .next(),
+        &SExpr::Located(_, ref expr) => unreadable_expr(expr),
+        _ => None,
+    }
+}
+
+/// Like `print_expr`, but values without a literal are a type error instead of
+/// being printed as something which does not read back to them
+pub fn print_readable(expr: &SExpr) -> Result<String, DovahkiinError> {
+    match unreadable_expr(expr) {
+        Some(reason) => Err(DovahkiinError::type_error(reason)),
+        None => Ok(print_expr(expr)),
+    }
+}
+
+/// Forms wider than `width` are broken into indented lines
+pub fn print_expr_with_width(expr: &SExpr, width: usize) -> String {
+    print_doc(expr_doc(expr), width)
+}
+
+/// Prints a value as a literal which evaluates back to the value, except arrays which are
+/// printed as vectors and evaluate to them. NaN, infinities, NA and map entries only set by
+/// key id have no literal, `print_readable` rejects them.
+pub fn print_value(value: &Value) -> String {
+    print_value_with_width(value, DEFAULT_WIDTH)
+}
+
+pub fn print_value_with_width(value: &Value, width: usize) -> String {
+    print_doc(value_doc(value), width)
+}
+
+/// Prints forms one per line, like a source file
+pub fn print_exprs(exprs: &[SExpr]) -> String {
+    exprs.iter().map(print_expr).collect::<Vec<_>>().join("\n")
+}
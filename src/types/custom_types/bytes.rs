@@ -40,10 +40,61 @@ impl DerefMut for SmallBytes {
     }
 }
 
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
 impl Bytes {
     pub fn from_vec(vec: Vec<u8>) -> Bytes {
         Bytes { data: vec }
     }
+    /// Decodes standard base64, padding is optional
+    pub fn from_base64(str: &str) -> Option<Bytes> {
+        let encoded = str.trim_end_matches('=');
+        if str.len() - encoded.len() > 2 || encoded.len() % 4 == 1 {
+            return None;
+        }
+        let mut data = Vec::with_capacity(encoded.len() * 3 / 4);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in encoded.bytes() {
+            buffer = (buffer << 6) | base64_value(c)?;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                data.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        Some(Bytes::from_vec(data))
+    }
+    /// Encodes in standard base64 with padding
+    pub fn to_base64(&self) -> String {
+        let mut str = String::with_capacity((self.data.len() + 2) / 3 * 4);
+        for chunk in self.data.chunks(3) {
+            let buffer = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |buffer, (i, &b)| buffer | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    str.push(BASE64_CHARS[(buffer >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    str.push('=');
+                }
+            }
+        }
+        str
+    }
     pub fn to<'a, T>(&'a self) -> T
     where
         T: serde::Deserialize<'a>,
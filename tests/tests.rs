@@ -1,7 +1,29 @@
+use bifrost_hasher::hash_str;
+use dovahkiin::error::{DovahkiinError, ErrorKind};
+use dovahkiin::expr::interpreter::{Envorinment, Interpreter, Limits, Scoping};
+use dovahkiin::expr::symbols::{new_symbol, Symbol};
+use dovahkiin::expr::wire;
 use dovahkiin::expr::SExpr;
+use dovahkiin::integrated::json;
 use dovahkiin::integrated::lisp;
+use dovahkiin::integrated::sql;
+use dovahkiin::lexer::lisp as lexer;
+use dovahkiin::parser::cst;
+use dovahkiin::parser::lisp::ParserOptions;
+use dovahkiin::printer::format;
+use dovahkiin::printer::lisp as printer;
+use dovahkiin::types;
+use dovahkiin::types::key_hash;
 use dovahkiin::types::OwnedValue as Value;
+use dovahkiin::types::Type;
+use dovahkiin::types::{Bytes, Id, Pos2d32, Pos3d64};
+use std::io::Cursor;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
 
+extern crate bifrost_hasher;
 extern crate dovahkiin;
 
 #[test]
@@ -132,9 +154,1469 @@ pub fn scoping() {
     let str_function = "(def x 1u32)\n
                         (defunc y [] x)\n
                         (let [x 2u32] (y))";
-    // 2 for dynamic scoping, 1 for lexical scoping. Dovahkiin is dynamic scoping
+    // 2 for dynamic scoping, 1 for lexical scoping
+    assert_eq!(
+        lisp::eval_string(&interpreter, str_function).unwrap(),
+        SExpr::Value(Value::U32(1))
+    );
+    let interpreter = lisp::get_interpreter();
+    interpreter.set_scoping(Scoping::Dynamic);
     assert_eq!(
         lisp::eval_string(&interpreter, str_function).unwrap(),
         SExpr::Value(Value::U32(2))
     );
 }
+
+#[test]
+pub fn token_spans() {
+    let tokens = lexer::tokenize_str("(+ 1u32\n  x)").unwrap();
+    let positions: Vec<(usize, usize)> = tokens
+        .iter()
+        .map(|t| (t.span.start.line, t.span.start.column))
+        .collect();
+    assert_eq!(positions, vec![(1, 1), (1, 2), (1, 4), (2, 3), (2, 4)]);
+    assert_eq!(tokens[2].span.end.column, 8);
+}
+
+#[test]
+pub fn form_spans() {
+    let exprs = lisp::parse_to_expr("(def x 1u32)\n (+ x 1u32)").unwrap();
+    let span = exprs[1].span().unwrap();
+    assert_eq!((span.start.line, span.start.column), (2, 2));
+    assert_eq!((span.end.line, span.end.column), (2, 12));
+}
+
+#[test]
+pub fn error_positions() {
+    let interpreter = lisp::get_interpreter();
+    let lex_err = lisp::parse_to_expr("(+ 1u32 2x32)").err().unwrap();
+    assert_eq!(lex_err.kind, ErrorKind::Lex);
+    assert_eq!(lex_err.span.unwrap().start.column, 10);
+    let parse_err = lisp::parse_to_expr("(do\n  (+ 1u32 2u32)").err().unwrap();
+    assert_eq!(parse_err.kind, ErrorKind::Incomplete);
+    assert_eq!(parse_err.span.unwrap().start.line, 1);
+    let eval_err = lisp::eval_string(&interpreter, "(do\n  (+ 1u32 2i32))")
+        .err()
+        .unwrap();
+    let span = eval_err.span.unwrap();
+    assert_eq!((span.start.line, span.start.column), (2, 3));
+    assert!(eval_err.to_string().ends_with("at 2:3"), "{}", eval_err);
+}
+
+#[test]
+pub fn error_kinds() {
+    let interpreter = lisp::get_interpreter();
+    let kind_of = |code| lisp::eval_string(&interpreter, code).err().unwrap().kind;
+    assert_eq!(kind_of("(undefined-fn 1u32)"), ErrorKind::UnknownSymbol);
+    assert_eq!(kind_of("(when true)"), ErrorKind::Arity);
+    assert_eq!(kind_of("(+ 1u32 \"a\")"), ErrorKind::Type);
+}
+
+#[test]
+pub fn error_cause_chain() {
+    let interpreter = lisp::get_interpreter();
+    let err = lisp::eval_string(
+        &interpreter,
+        "(defunc add-one [x] (+ x 1u32))\n(add-one 1i32)",
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.kind, ErrorKind::Type);
+    assert_eq!(err.span.unwrap().start.line, 2);
+    let cause = err.cause.as_ref().unwrap();
+    assert_eq!(cause.span.unwrap().start.column, 21);
+    assert_eq!(err.root_cause(), cause.as_ref());
+}
+
+#[test]
+pub fn bound_value_as_function() {
+    let interpreter = lisp::get_interpreter();
+    let str_function = "(def m (hash-map \"a\" 1u32)) (m \"a\")";
+    assert_eq!(
+        lisp::eval_string(&interpreter, str_function).unwrap(),
+        SExpr::Value(Value::U32(1))
+    );
+}
+
+#[test]
+pub fn comments() {
+    let interpreter = lisp::get_interpreter();
+    let str_function = "; adds things up\n\
+                        (+ 1u32 ; first\n\
+                        #| block #| nested |# comment |# 2u32\n\
+                        #_ (this form is ignored) 3u32 #_4u32)";
+    assert_eq!(
+        lisp::eval_string(&interpreter, str_function).unwrap(),
+        SExpr::Value(Value::U32(6))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(size [1u32 #_ #_ 2u32 3u32 4u32]) ;; end").unwrap(),
+        SExpr::Value(Value::U64(2))
+    );
+}
+
+#[test]
+pub fn comment_errors() {
+    let err = lisp::parse_to_expr("(+ 1u32 #_)").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+    assert_eq!(err.span.unwrap().start.column, 9);
+    let err = lisp::parse_to_expr("#| never closed").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Incomplete);
+}
+
+#[test]
+pub fn quoting() {
+    let interpreter = lisp::get_interpreter();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "'x").unwrap(),
+        SExpr::ISymbol(hash_str("x"), "x".to_string())
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "'(+ 1u32 2u32)").unwrap(),
+        SExpr::List(vec![
+            SExpr::ISymbol(hash_str("+"), "+".to_string()),
+            SExpr::Value(Value::U32(1)),
+            SExpr::Value(Value::U32(2)),
+        ])
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(eval '(+ 1u32 2u32))").unwrap(),
+        SExpr::Value(Value::U32(3))
+    );
+}
+
+#[test]
+pub fn quasiquoting() {
+    let interpreter = lisp::get_interpreter();
+    let str_function = "(def x 2u32)\
+                        (def xs [3u32 4u32])\
+                        `(+ 1u32 ~x ~@xs)";
+    assert_eq!(
+        lisp::eval_string(&interpreter, str_function).unwrap(),
+        SExpr::List(vec![
+            SExpr::ISymbol(hash_str("+"), "+".to_string()),
+            SExpr::Value(Value::U32(1)),
+            SExpr::Value(Value::U32(2)),
+            SExpr::Value(Value::U32(3)),
+            SExpr::Value(Value::U32(4)),
+        ])
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(eval `(* ~x ~@xs))").unwrap(),
+        SExpr::Value(Value::U32(24))
+    );
+    let err = lisp::eval_string(&interpreter, "~x").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Runtime);
+}
+
+#[test]
+pub fn map_literals() {
+    let interpreter = lisp::get_interpreter();
+    let str_function = "(def age 30u32)\
+                        (def person { \"name\" \"Alduin\" :age (+ age 1u32) :tags [1u8 2u8] })";
+    lisp::eval_string(&interpreter, str_function).unwrap();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(person \"name\")").unwrap(),
+        SExpr::Value(Value::String("Alduin".to_string()))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(person \"age\")").unwrap(),
+        SExpr::Value(Value::U32(31))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(person \"tags\")").unwrap(),
+        SExpr::Value(Value::Array(vec![Value::U8(1), Value::U8(2)]))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(size {})").unwrap(),
+        SExpr::Value(Value::U64(0))
+    );
+    let err = lisp::parse_to_expr("{ \"name\" }").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+    let err = lisp::parse_to_expr("{ 1u32 2u32 }").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+}
+
+#[test]
+pub fn keywords() {
+    let interpreter = lisp::get_interpreter();
+    let str_function = "(def row { :name \"Paarthurnax\" \"age\" 1000u32 })";
+    lisp::eval_string(&interpreter, str_function).unwrap();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(:name row)").unwrap(),
+        SExpr::Value(Value::String("Paarthurnax".to_string()))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(row :age)").unwrap(),
+        SExpr::Value(Value::U32(1000))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(:missing row)").unwrap(),
+        SExpr::Value(Value::Null)
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, ":name").unwrap(),
+        SExpr::Keyword(key_hash("name"), "name".to_string())
+    );
+    let tokens = lexer::tokenize_str(":name").unwrap();
+    assert_eq!(tokens[0].token.to_string(), ":name");
+}
+
+#[test]
+pub fn unsuffixed_numbers() {
+    let interpreter = lisp::get_interpreter();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(+ 1 2)").unwrap(),
+        SExpr::Value(Value::I64(3))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(* 2.5 2)").unwrap(),
+        SExpr::Value(Value::F64(5.0))
+    );
+    lisp::eval_string(&interpreter, "(def x 41u32)").unwrap();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(+ x 1)").unwrap(),
+        SExpr::Value(Value::U32(42))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(>= 100 x)").unwrap(),
+        SExpr::Value(Value::Bool(true))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(- 1.5f32 1)").unwrap(),
+        SExpr::Value(Value::F32(0.5))
+    );
+    let err = lisp::eval_string(&interpreter, "(+ 1u8 300)")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind, ErrorKind::Type);
+    let err = lisp::eval_string(&interpreter, "(+ x 0.5)").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Type);
+    let options = ParserOptions {
+        default_int_type: Type::U16,
+        default_float_type: Type::F32,
+    };
+    let exprs = lisp::parse_to_expr_with_options("(+ 1 2)", &options).unwrap();
+    assert_eq!(
+        lisp::eval(&interpreter, exprs).unwrap(),
+        SExpr::Value(Value::U16(3))
+    );
+    let exprs = lisp::parse_to_expr_with_options("2.5", &options).unwrap();
+    assert_eq!(
+        lisp::eval(&interpreter, exprs).unwrap(),
+        SExpr::Value(Value::F32(2.5))
+    );
+}
+
+#[test]
+pub fn number_literals() {
+    let interpreter = lisp::get_interpreter();
+    let cases = vec![
+        ("0xFFu64", Value::U64(255)),
+        ("0xffu8", Value::U8(255)),
+        ("-0x10i8", Value::I8(-16)),
+        ("0b1010u8", Value::U8(10)),
+        ("0o755u16", Value::U16(493)),
+        ("1_000_000u64", Value::U64(1_000_000)),
+        ("0x_dead_beef", Value::I64(0xdead_beef)),
+        ("6.02e23f64", Value::F64(6.02e23)),
+        ("1.5E-3f32", Value::F32(1.5e-3)),
+        ("1e3", Value::F64(1000.0)),
+        ("2f32", Value::F32(2.0)),
+    ];
+    for (code, value) in cases {
+        assert_eq!(
+            lisp::eval_string(&interpreter, code).unwrap(),
+            SExpr::Value(value),
+            "{}",
+            code
+        );
+    }
+    let err = lisp::parse_to_expr("256u8").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+    assert_eq!(err.message, "Number 256 is out of range for u8");
+    let err = lisp::parse_to_expr("0x1_0000u16").err().unwrap();
+    assert_eq!(err.message, "Number 0x1_0000 is out of range for u16");
+    let err = lisp::parse_to_expr("1e400f64").err().unwrap();
+    assert_eq!(err.message, "Number 1e400 is out of range for f64");
+    for code in vec!["0b102u8", "0x1.5", "0b1f32", "1e", "0x", "1.5u32", "1.2.3"] {
+        let err = lisp::parse_to_expr(code).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Lex, "{}", code);
+    }
+}
+
+#[test]
+pub fn bool_nil_and_char_literals() {
+    let interpreter = lisp::get_interpreter();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "true").unwrap(),
+        SExpr::Value(Value::Bool(true))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(if false 1u8 2u8)").unwrap(),
+        SExpr::Value(Value::U8(2))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(if nil 1u8 2u8)").unwrap(),
+        SExpr::Value(Value::U8(2))
+    );
+    let exprs = lisp::parse_to_expr("[\\a \\newline \\u{1F600} \\( \\space]").unwrap();
+    assert_eq!(
+        exprs[0],
+        SExpr::Vec(vec![
+            SExpr::Value(Value::Char('a')),
+            SExpr::Value(Value::Char('\n')),
+            SExpr::Value(Value::Char('😀')),
+            SExpr::Value(Value::Char('(')),
+            SExpr::Value(Value::Char(' ')),
+        ])
+    );
+    let tokens = lexer::tokenize_str("\\newline \\u{7}").unwrap();
+    assert_eq!(tokens[0].token.to_string(), "\\newline");
+    assert_eq!(tokens[1].token.to_string(), "\\u{7}");
+    for code in vec!["\\bell", "\\u{110000}"] {
+        let err = lisp::parse_to_expr(code).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Lex, "{}", code);
+    }
+}
+
+#[test]
+pub fn tagged_literals() {
+    let interpreter = lisp::get_interpreter();
+    assert_eq!(
+        lisp::eval_string(&interpreter, "#id \"0000000000000001-00000000000000ff\"").unwrap(),
+        SExpr::Value(Value::Id(Id::new(1, 255)))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "#pos2d [1.0f32 2.0f32]").unwrap(),
+        SExpr::Value(Value::Pos2d32(Pos2d32 { x: 1.0, y: 2.0 }))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "#pos3d64 [1.5 2 -3.0f64]").unwrap(),
+        SExpr::Value(Value::Pos3d64(Pos3d64 {
+            x: 1.5,
+            y: 2.0,
+            z: -3.0
+        }))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "#bytes \"RG92YWhraWlu\"").unwrap(),
+        SExpr::Value(Value::Bytes(Bytes::from_vec(b"Dovahkiin".to_vec())))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "#bytes \"AAE=\"").unwrap(),
+        SExpr::Value(Value::Bytes(Bytes::from_vec(vec![0, 1])))
+    );
+    assert_eq!(
+        lisp::eval_string(&interpreter, "(= #id \"1-2\" #id \"0001-0002\")").unwrap(),
+        SExpr::Value(Value::Bool(true))
+    );
+    for code in vec![
+        "#id \"12\"",
+        "#pos2d [1.0f64 2.0f64]",
+        "#pos3d [1.0 2.0]",
+        "#bytes \"A\"",
+        "#uuid \"1\"",
+    ] {
+        let err = lisp::parse_to_expr(code).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Parse, "{}", code);
+    }
+}
+
+#[test]
+pub fn string_literals() {
+    let interpreter = lisp::get_interpreter();
+    let cases = vec![
+        ("\"a\\\\b\"", "a\\b"),
+        ("\"tab\\tquote\\\"\"", "tab\tquote\""),
+        ("\"\\u{1F600}\\u{41}\"", "😀A"),
+        ("\"\\x41\"", "A"),
+        ("\"line one\nline two\"", "line one\nline two"),
+        ("\"joined \\\n     here\"", "joined here"),
+        ("r\"C:\\path\\d+\"", "C:\\path\\d+"),
+        ("r#\"say \"hi\"\"#", "say \"hi\""),
+        ("r##\"a \"# b\"##", "a \"# b"),
+    ];
+    for (code, expected) in cases {
+        assert_eq!(
+            lisp::eval_string(&interpreter, code).unwrap(),
+            SExpr::Value(Value::String(expected.to_string())),
+            "{}",
+            code
+        );
+    }
+    assert_eq!(
+        lisp::eval_string(&interpreter, "b\"ab\\x00\\xff\\n\"").unwrap(),
+        SExpr::Value(Value::Bytes(Bytes::from_vec(vec![
+            b'a', b'b', 0, 0xff, b'\n'
+        ])))
+    );
+    let tokens = lexer::tokenize_str("\"a\\\"b\\n\" b\"\\x00z\"").unwrap();
+    assert_eq!(tokens[0].token.to_string(), "\"a\\\"b\\n\"");
+    assert_eq!(tokens[1].token.to_string(), "b\"\\x00z\"");
+    let err = lisp::parse_to_expr("(concat \"abc").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Incomplete);
+    assert_eq!(err.message, "Unterminated string");
+    assert_eq!(err.span.unwrap().start.column, 9);
+    for code in vec![
+        "\"\\u1F600\"",
+        "\"\\u{110000}\"",
+        "\"\\xff\"",
+        "b\"\\u{41}\"",
+        "b\"é\"",
+        "\"\\q\"",
+    ] {
+        let err = lisp::parse_to_expr(code).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Lex, "{}", code);
+    }
+}
+
+#[test]
+pub fn incomplete_input() {
+    let interpreter = lisp::get_interpreter();
+    for code in vec![
+        "(+ 1u32",
+        "(do [1u32 {\"a\"",
+        "'",
+        "#id",
+        "\"abc",
+        "r#\"abc\"",
+        "#| open",
+        "(+ 1u32 #_",
+        "\\",
+    ] {
+        let err = lisp::parse_to_expr(code).err().unwrap();
+        assert!(err.is_incomplete(), "{}: {}", code, err);
+    }
+    // a REPL keeps buffering lines until the form is complete
+    let mut buffer = String::new();
+    let mut result = None;
+    for line in vec!["(+ 1u32", "   2u32", "   \"x\")"] {
+        buffer.push_str(line);
+        buffer.push('\n');
+        match lisp::parse_to_expr(&buffer) {
+            Ok(exprs) => result = Some(lisp::eval(&interpreter, exprs)),
+            Err(ref e) if e.is_incomplete() => continue,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert_eq!(result.unwrap().err().unwrap().kind, ErrorKind::Type);
+    let err = lisp::parse_to_expr("(+ 1u32 2u32))").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+}
+
+#[test]
+pub fn read_from_reader() {
+    let interpreter = lisp::get_interpreter();
+    let code = "(def x 1u32) ; first\n(def y\n  2u32)\n(+ x y)\n";
+    assert_eq!(
+        lisp::eval_reader(&interpreter, Cursor::new(code)).unwrap(),
+        SExpr::Value(Value::U32(3))
+    );
+    let forms: Vec<_> = lisp::read_forms(Cursor::new(code)).collect();
+    assert_eq!(forms.len(), 3);
+    let span = forms[2].as_ref().unwrap().span().unwrap();
+    assert_eq!((span.start.line, span.start.column), (4, 1));
+    assert_eq!(span.start.offset, code.find("(+ x y)").unwrap());
+    let mut forms = lisp::read_forms(Cursor::new("(+ 1u32 2u32) (+ 1u32"));
+    assert!(forms.next().unwrap().is_ok());
+    assert!(forms.next().unwrap().err().unwrap().is_incomplete());
+    assert!(forms.next().is_none());
+    let tokens: Vec<_> = lexer::TokenReader::new(Cursor::new("(a \"b\")"))
+        .map(|t| t.unwrap().token.to_string())
+        .collect();
+    assert_eq!(tokens, vec!["(", "a", "\"b\"", ")"]);
+}
+
+#[test]
+pub fn printing() {
+    let code = "(defunc area [shape] (let [w (:w shape) h (\"h\" shape)] (* w h 0.5 2)))";
+    let exprs = lisp::parse_to_expr(code).unwrap();
+    assert_eq!(printer::print_expr(&exprs[0]), code);
+    let narrow = printer::print_expr_with_width(&exprs[0], 40);
+    assert_eq!(
+        narrow,
+        "(defunc area [shape]\n  (let [w (:w shape) h (\"h\" shape)]\n    (* w h 0.5 2)))"
+    );
+    let reread = lisp::parse_to_expr(&narrow).unwrap();
+    assert_eq!(
+        reread[0].clone().strip_locations(),
+        exprs[0].clone().strip_locations()
+    );
+    let code = "(do 'x `(a ~b) [1u8 -2i16 3.5f32 1e300f64 \"q\\\"\\n\" \\a \\newline nil true] \
+                r\"raw\\d\" b\"\\x00\" {\"k\" :v} #id \"1-2\" #pos2d [1.0f32 2.5f32])";
+    let exprs = lisp::parse_to_expr(code).unwrap();
+    let printed = printer::print_exprs(&exprs);
+    let reread = lisp::parse_to_expr(&printed).unwrap();
+    assert_eq!(
+        reread[0].clone().strip_locations(),
+        exprs[0].clone().strip_locations(),
+        "{}",
+        printed
+    );
+    let interpreter = lisp::get_interpreter();
+    let lambda = lisp::eval_string(&interpreter, "(lambda [x] (+ x 1u32))").unwrap();
+    assert_eq!(lambda.to_string(), "(lambda [x] (+ x 1u32))");
+    let value = lisp::eval_string(
+        &interpreter,
+        "{\"name\" \"Alduin\" \"tags\" [1u8 2u8] \"inner\" {\"id\" #id \"ab-cd\"}}",
+    )
+    .unwrap();
+    if let SExpr::Value(ref value) = value {
+        let printed = printer::print_value_with_width(value, 20);
+        assert!(printed.contains('\n'), "{}", printed);
+        // field order of maps from hash-map is not stable
+        match (lisp::eval_string(&interpreter, &printed).unwrap(), value) {
+            (SExpr::Value(Value::Map(reread)), &Value::Map(ref map)) => {
+                assert_eq!(reread.into_string_map(), map.clone().into_string_map())
+            }
+            (reread, _) => panic!("{:?}", reread),
+        }
+    } else {
+        panic!("{:?}", value);
+    }
+}
+
+#[test]
+pub fn concrete_syntax_tree() {
+    let code = "; header\n(def x   0xFFu64) #| block |#\n\n'( a #_ b  \"s\\n\" ) \r\n";
+    let nodes = cst::parse_cst(code).unwrap();
+    assert_eq!(cst::to_source(&nodes), code);
+    let atoms: Vec<String> = match nodes[2] {
+        cst::Node::Group { ref children, .. } => children
+            .iter()
+            .filter_map(|node| match node {
+                &cst::Node::Atom(ref leaf) => Some(leaf.text.clone()),
+                _ => None,
+            })
+            .collect(),
+        ref node => panic!("{:?}", node),
+    };
+    assert_eq!(atoms, vec!["def", "x", "0xFFu64"]);
+    assert!(cst::parse_cst("(a]").err().is_some());
+    assert!(cst::parse_cst("(a").err().unwrap().is_incomplete());
+}
+
+#[test]
+pub fn source_formatting() {
+    let code = "; area of shapes\n(defunc   area [shape]   ; doc\n  (* (:w shape)\n\n (:h shape)))\n\n\n\n(area {:w 1.0 :h 2.0})";
+    let formatted = format::format_source(code).unwrap();
+    assert_eq!(
+        formatted,
+        "; area of shapes\n(defunc area [shape] ; doc\n  (* (:w shape) (:h shape)))\n\n(area {:w 1.0 :h 2.0})\n"
+    );
+    assert_eq!(format::format_source(&formatted).unwrap(), formatted);
+    let code = "(let [first-value 0x_FF_u64 second \"a long string\"] #| keep |# (+ first-value \
+                second) ; trailing\n)";
+    let formatted = format::format_source_with_width(code, 30).unwrap();
+    assert_eq!(
+        formatted,
+        "(let [first-value\n      0x_FF_u64\n      second\n      \"a long string\"] #| keep |#\n  (+ first-value second) ; trailing\n  )\n"
+    );
+    // comments are never dropped and the forms read the same
+    for (before, after) in vec![(code, &formatted)] {
+        let comments = |code: &str| -> Vec<String> {
+            lexer::tokenize_str(code)
+                .unwrap()
+                .into_iter()
+                .filter_map(|t| match t.token {
+                    lexer::Token::LineComment(c) | lexer::Token::BlockComment(c) => Some(c),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(comments(before), comments(after));
+        let strip = |code: &str| -> Vec<SExpr> {
+            lisp::parse_to_expr(code)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.strip_locations())
+                .collect()
+        };
+        assert_eq!(strip(before), strip(after));
+    }
+    assert_eq!(format::format_source("`(a ~ @b)").unwrap(), "`(a ~ @b)\n");
+}
+
+#[test]
+pub fn wire_encoding() {
+    let interpreter = lisp::get_interpreter();
+    let code = "(let [scale 3u32] (map (lambda [x] (* x scale)) [1 2 #id \"01-02\" :k \"s\"]))";
+    for expr in lisp::parse_to_expr(code).unwrap() {
+        let decoded = wire::decode(&wire::encode(&expr)).unwrap();
+        assert_eq!(decoded, expr);
+        assert_eq!(decoded.span(), expr.span());
+    }
+    // compiled lambdas keep their interned symbols
+    let lambda = lisp::eval_string(&interpreter, "(lambda [x] (* x 2u32))").unwrap();
+    match lambda {
+        SExpr::LAMBDA(ref params, ..) => match params[0].unlocated() {
+            &SExpr::ISymbol(id, ref name) => assert_eq!((id, name.as_str()), (hash_str("x"), "x")),
+            param => panic!("{:?}", param),
+        },
+        ref expr => panic!("{:?}", expr),
+    }
+    let decoded = wire::decode(&wire::encode(&lambda)).unwrap();
+    assert_eq!(decoded, lambda);
+    let call = SExpr::List(vec![decoded, SExpr::Value(Value::U32(21))]);
+    assert_eq!(
+        lisp::eval(&interpreter, vec![call]).unwrap(),
+        SExpr::Value(Value::U32(42))
+    );
+
+    let data = wire::encode(&SExpr::Symbol("a".to_string()));
+    let mut other_version = data.clone();
+    other_version[3] += 1;
+    let err = wire::decode(&other_version).err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Decode);
+    let version = format!("version {}", wire::VERSION + 1);
+    assert!(err.message.contains(&version), "{}", err);
+    let mut damaged = data.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        wire::decode(&damaged).err().unwrap().kind,
+        ErrorKind::Decode
+    );
+    assert_eq!(
+        wire::decode(&data[..4]).err().unwrap().kind,
+        ErrorKind::Decode
+    );
+}
+
+#[test]
+pub fn json_frontend() {
+    let interpreter = lisp::get_interpreter();
+    let eval_json = |code: &str| json::eval_string(&interpreter, code).unwrap();
+    assert_eq!(
+        eval_json(r#"["+", {"u32": 1}, {"u32": 2}]"#),
+        SExpr::Value(Value::U32(3))
+    );
+    assert_eq!(
+        eval_json(r#"{"let": [["x", {"f32": 2}], ["*", "x", 0.5]]}"#),
+        SExpr::Value(Value::F32(1.0))
+    );
+    // lowered to the same tree as the lisp code
+    let pairs = vec![
+        (
+            r#"{"let": [["x", 1], ["+", "x", 2.5]]}"#,
+            "(let [x 1] (+ x 2.5))",
+        ),
+        (
+            r#"{"defunc": ["f", ["a"], ["-", "a", {"i8": -1}]]}"#,
+            "(defunc f [a] (- a -1i8))",
+        ),
+        (
+            r#"["filter", {"lambda": [["r"], [{"$keyword": "ok"}, "r"]]}, {"$vec": [{"$map": {":ok": true, "n": null}}]}]"#,
+            "(filter (lambda [r] (:ok r)) [{:ok true \"n\" nil}])",
+        ),
+        (
+            r#"{"map": [{"lambda": [["x"], ["*", "x", 2]]}, {"$vec": [1, 2]}]}"#,
+            "(map (lambda [x] (* x 2)) [1 2])",
+        ),
+        (
+            r#"[{"id": "1-ff"}, {"bytes": "aGk="}, {"pos2d32": [1, 2.5]}, {"char": "c"}, {"string": "s"}]"#,
+            "(#id \"1-ff\" #bytes \"aGk=\" #pos2d32 [1 2.5] \\c \"s\")",
+        ),
+    ];
+    for (json_code, lisp_code) in pairs {
+        let expr = json::parse_to_expr(json_code).unwrap();
+        let lisp_expr = lisp::parse_to_expr(lisp_code)
+            .unwrap()
+            .remove(0)
+            .strip_locations();
+        assert_eq!(expr, lisp_expr);
+        // exporting gives the same JSON form
+        let exported = json::to_json(&lisp_expr).unwrap();
+        assert_eq!(
+            json::parse_to_expr(&exported).unwrap(),
+            expr,
+            "{}",
+            exported
+        );
+    }
+    // string fields which look like keywords still read back as strings,
+    // field order of maps from hash-map is not stable
+    let map = match lisp::eval_string(&interpreter, "{\":k\" 1u32 :n \"s\"}").unwrap() {
+        SExpr::Value(Value::Map(map)) => map,
+        map => panic!("{:?}", map),
+    };
+    let exported = json::to_json(&SExpr::Value(Value::Map(map.clone()))).unwrap();
+    assert!(exported.starts_with(r#"["hash-map","#), "{}", exported);
+    assert!(exported.contains(r#"{"string":":k"}"#), "{}", exported);
+    match json::eval_string(&interpreter, &exported).unwrap() {
+        SExpr::Value(Value::Map(reread)) => {
+            assert_eq!(reread.into_string_map(), map.into_string_map())
+        }
+        reread => panic!("{:?}", reread),
+    }
+    assert_eq!(
+        json::to_json(&lisp::parse_to_expr("(let [x 1u32] [x 2.5 :k])").unwrap()[0]).unwrap(),
+        r#"{"let":[["x",{"u32":1}],{"$vec":["x",2.5,{"$keyword":"k"}]}]}"#
+    );
+
+    let error = |code: &str| json::parse_to_expr(code).err().unwrap();
+    assert_eq!(error("[\"+\", 1").kind, ErrorKind::Parse);
+    assert!(error(r#"{"let": [["x", {"u8": 256}]]}"#)
+        .message
+        .contains("out of range for u8, at /let/0/1/u8"));
+    assert!(error(r#"["f", {"a": 1, "b": 2}]"#)
+        .message
+        .ends_with("at /1"));
+    assert!(error(r#"{"id": "zz"}"#)
+        .message
+        .contains("Invalid value for id"));
+    assert!(error(r#"{"u32": 1.5}"#)
+        .message
+        .contains("Invalid value for u32"));
+}
+
+#[test]
+pub fn sql_frontend() {
+    let interpreter = lisp::get_interpreter();
+    lisp::eval_string(
+        &interpreter,
+        "(def people [{:name \"ann\" :age 31u32 :city {:name \"oslo\"}}
+                      {:name \"bob\" :age 17u32 :city {:name \"rome\"}}
+                      {:name \"cid\" :age 45u32 :city {:name \"oslo\"}}
+                      {:name \"dan\" :age 31u32 :city {:name \"rome\"}}])",
+    )
+    .unwrap();
+    let names = |code: &str| -> Vec<String> {
+        match sql::eval_string(&interpreter, code).unwrap() {
+            SExpr::Vec(rows) => rows
+                .into_iter()
+                .map(|row| match row {
+                    SExpr::Value(ref row) => row["name"].string().unwrap().clone(),
+                    row => panic!("{:?}", row),
+                })
+                .collect(),
+            result => panic!("{:?}", result),
+        }
+    };
+    assert_eq!(
+        names("SELECT name FROM people WHERE age > 18 ORDER BY age DESC, name LIMIT 2"),
+        vec!["cid", "ann"]
+    );
+    assert_eq!(
+        names("select * from people where not (city.name = 'oslo' or age < 18u32)"),
+        vec!["dan"]
+    );
+    assert_eq!(
+        names(
+            "SELECT name FROM (SELECT * FROM people WHERE age >= 31) \
+             WHERE city.name != 'rome' AND age * 2 - 50 > 10 ORDER BY name DESC;"
+        ),
+        vec!["cid", "ann"]
+    );
+    match sql::eval_string(
+        &interpreter,
+        "SELECT age * 2 AS double, size(name), age - 1 FROM people LIMIT 1",
+    )
+    .unwrap()
+    {
+        SExpr::Vec(rows) => match rows[0] {
+            SExpr::Value(ref row) => {
+                assert_eq!(row["double"], Value::U32(62));
+                assert_eq!(row["_3"], Value::U32(30));
+                assert!(row["size"] != Value::Null);
+            }
+            ref row => panic!("{:?}", row),
+        },
+        result => panic!("{:?}", result),
+    }
+    // lowered to the same forms as the lisp code
+    assert_eq!(
+        sql::parse_to_expr("SELECT * FROM people WHERE age > 18 AND name != 'bob' LIMIT 5")
+            .unwrap()
+            .strip_locations(),
+        lisp::parse_to_expr(
+            "(take 5 (filter (lambda [row] (and (> (:age row) 18) (not (= (:name row) \"bob\")))) \
+             people))"
+        )
+        .unwrap()
+        .remove(0)
+        .strip_locations()
+    );
+
+    let err = sql::parse_to_expr("SELECT name FROM").err().unwrap();
+    assert!(err.is_incomplete());
+    let err = sql::parse_to_expr("SELECT name, FROM people")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind, ErrorKind::Parse);
+    assert_eq!(err.span.unwrap().start.column, 14);
+    let err = sql::eval_string(&interpreter, "SELECT * FROM people ORDER BY city")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind, ErrorKind::Type);
+    // negating a column keeps its type, unsigned ones cannot be negated
+    let err = sql::eval_string(&interpreter, "SELECT -age FROM people")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind, ErrorKind::Type);
+    lisp::eval_string(&interpreter, "(def temps [{:t -3i32} {:t 0.5f64}])").unwrap();
+    match sql::eval_string(&interpreter, "SELECT -t AS t FROM temps").unwrap() {
+        SExpr::Vec(rows) => {
+            let negated: Vec<Value> = rows
+                .into_iter()
+                .map(|row| match row {
+                    SExpr::Value(ref row) => row["t"].clone(),
+                    row => panic!("{:?}", row),
+                })
+                .collect();
+            assert_eq!(negated, vec![Value::I32(3), Value::F64(-0.5)]);
+        }
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+pub fn closures() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    // the returned lambda keeps n after make-adder returned
+    eval("(defunc make-adder [n] (lambda [x] (+ x n)))");
+    eval("(def add2 (make-adder 2u32))");
+    eval("(def add5 (make-adder 5u32))");
+    assert_eq!(eval("(add2 1u32)"), SExpr::Value(Value::U32(3)));
+    assert_eq!(eval("(add5 1u32)"), SExpr::Value(Value::U32(6)));
+    // the caller's x is not seen by the closure
+    assert_eq!(
+        eval("(let [x 100u32 n 100u32] (add2 x))"),
+        SExpr::Value(Value::U32(102))
+    );
+    // higher-order functions get the bindings of the calling scope
+    eval("(defunc twice [f x] (f (f x)))");
+    assert_eq!(
+        eval("(let [f 1u32 x 3u32] (twice (lambda [y] (* y x)) 2u32))"),
+        SExpr::Value(Value::U32(18))
+    );
+    // arguments are evaluated where the call is written
+    assert_eq!(
+        eval("(let [a 1u32] ((lambda [a b] (+ a b)) 10u32 (+ a 1u32)))"),
+        SExpr::Value(Value::U32(12))
+    );
+    assert_eq!(
+        eval("(let [x 1u32 y (+ x 1u32) x 5u32] (+ x y))"),
+        SExpr::Value(Value::U32(7))
+    );
+    // let bindings are gone after the form, even when it failed
+    assert!(lisp::eval_string(&interpreter, "(let [z 1u32] (+ z \"a\"))").is_err());
+    assert_eq!(
+        eval("z"),
+        lisp::parse_to_expr("z").unwrap()[0].unlocated().clone()
+    );
+    // captured bindings travel with the encoded closure
+    let decoded = wire::decode(&wire::encode(&eval("add5"))).unwrap();
+    assert_eq!(
+        lisp::eval(
+            &interpreter,
+            vec![SExpr::List(vec![decoded, SExpr::Value(Value::U32(1))])]
+        )
+        .unwrap(),
+        SExpr::Value(Value::U32(6))
+    );
+}
+
+#[test]
+pub fn tail_calls() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    // deep enough to overflow the test thread stack without tail calls
+    eval(
+        "(defunc count [n acc] \
+           (if (= n 0u32) acc (count (- n 1u32) (+ acc 1u32))))",
+    );
+    assert_eq!(
+        eval("(count 100000u32 0u32)"),
+        SExpr::Value(Value::U32(100000))
+    );
+    // through cond, let, when and do, in mutual recursion
+    eval(
+        "(defunc ping [n] \
+           (cond (= n 0u32) :ping \
+                 true (let [m (- n 1u32)] (do (pong m)))))",
+    );
+    eval("(defunc pong [n] (if (= n 0u32) :pong (when true (ping (- n 1u32)))))");
+    assert_eq!(eval("(ping 100001u32)"), eval(":pong"));
+    // not in tail position, the result is still used by the caller
+    eval("(defunc sum [n] (if (= n 0u32) 0u32 (+ n (sum (- n 1u32)))))");
+    assert_eq!(eval("(sum 20u32)"), SExpr::Value(Value::U32(210)));
+    // errors name the function called in tail position
+    eval("(defunc fail [x] (+ x \"a\"))");
+    eval("(defunc call-fail [x] (fail x))");
+    let error = lisp::eval_string(&interpreter, "(call-fail 1u32)").unwrap_err();
+    assert!(error.to_string().contains("Error in function 'fail'"));
+}
+
+#[test]
+pub fn evaluation_limits() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code);
+    eval("(defunc forever [x] (forever x))").unwrap();
+    eval("(defunc deep [n] (if (= n 0u32) 0u32 (+ 1u32 (deep (- n 1u32)))))").unwrap();
+    interpreter.set_limits(Limits {
+        max_steps: Some(1000),
+        ..Limits::default()
+    });
+    let error = eval("(forever 1u32)").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+    let counters = match error.root_cause().payload {
+        Some(Value::Map(ref counters)) => counters.clone(),
+        ref payload => panic!("{:?}", payload),
+    };
+    assert_eq!(
+        counters.get("limit"),
+        &Value::String("step limit".to_string())
+    );
+    assert_eq!(counters.get("steps"), &Value::U64(1001));
+    // every evaluation gets the whole budget again
+    assert_eq!(eval("(+ 1u32 2u32)").unwrap(), SExpr::Value(Value::U32(3)));
+    interpreter.set_limits(Limits {
+        max_depth: Some(10),
+        ..Limits::default()
+    });
+    // (deep 9) down to (deep 0) are 10 nested calls
+    assert_eq!(eval("(deep 9u32)").unwrap(), SExpr::Value(Value::U32(9)));
+    let error = eval("(deep 10u32)").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+    assert!(error.root_cause().message.contains("call depth 11"));
+    // tail calls do not count as nested
+    eval("(defunc count [n] (if (= n 0u32) :done (count (- n 1u32))))").unwrap();
+    assert_eq!(eval("(count 100u32)").unwrap(), eval(":done").unwrap());
+    interpreter.set_limits(Limits {
+        timeout: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    });
+    let error = eval("(forever 1u32)").unwrap_err();
+    assert!(error.root_cause().message.contains("timeout"));
+}
+
+#[test]
+pub fn memory_quota() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code);
+    eval("(defunc grow [v n] (if (= n 0u32) v (grow (concat v v) (- n 1u32))))").unwrap();
+    interpreter.set_limits(Limits {
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    });
+    assert_eq!(
+        eval("(size (grow [1u32 2u32] 4u32))").unwrap(),
+        SExpr::Value(Value::U64(32))
+    );
+    // doubling the vector 40 times would take terabytes
+    let error = eval("(size (grow [1u32 2u32] 40u32))").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+    let counters = match error.root_cause().payload {
+        Some(Value::Map(ref counters)) => counters.clone(),
+        ref payload => panic!("{:?}", payload),
+    };
+    assert_eq!(
+        counters.get("limit"),
+        &Value::String("memory quota".to_string())
+    );
+    match counters.get("memory") {
+        &Value::U64(memory) => assert!(memory > 1 << 20 && memory < 1 << 22),
+        memory => panic!("{:?}", memory),
+    }
+    // maps built by map count too
+    interpreter.set_limits(Limits::default());
+    eval("(def rows (grow [1u32] 12u32))").unwrap();
+    interpreter.set_limits(Limits {
+        max_memory: Some(1 << 16),
+        ..Limits::default()
+    });
+    assert_eq!(eval("(size rows)").unwrap(), SExpr::Value(Value::U64(4096)));
+    let error = eval("(map (lambda [x] (hash-map :x x :name \"some name\")) rows)").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+    // each vector is counted once, before it is allocated
+    interpreter.set_limits(Limits::default());
+    eval("(def array (to_array rows))").unwrap();
+    interpreter.set_limits(Limits {
+        max_memory: Some(8192 * mem::size_of::<SExpr>()),
+        ..Limits::default()
+    });
+    assert_eq!(
+        eval("(size (concat array array))").unwrap(),
+        SExpr::Value(Value::U64(8192))
+    );
+    assert_eq!(
+        eval("(size (conj array 1u32))").unwrap(),
+        SExpr::Value(Value::U64(4097))
+    );
+    // and running out is not turned into another error
+    interpreter.set_limits(Limits {
+        max_memory: Some(1024),
+        ..Limits::default()
+    });
+    for code in &[
+        "(conj array 1u32)",
+        "(try (conj array 1u32) (catch e e))",
+        "(filter (lambda [x] true) rows)",
+    ] {
+        assert_eq!(
+            eval(code).unwrap_err().kind,
+            ErrorKind::LimitExceeded,
+            "{}",
+            code
+        );
+    }
+    // strings of errors caught count too
+    interpreter.set_limits(Limits {
+        max_memory: Some(16),
+        ..Limits::default()
+    });
+    let error = eval("(try (throw \"a message longer than the quota\") (catch e e))").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+}
+
+// Evaluates in an interpreter of its own, in the middle of the caller's evaluation
+#[derive(Debug)]
+struct NestedEval;
+
+impl Symbol for NestedEval {
+    fn eval(&self, _: Vec<SExpr>, _: &Envorinment) -> Result<SExpr, DovahkiinError> {
+        let interpreter = lisp::get_interpreter();
+        lisp::eval_string(&interpreter, "(def x 100u32)")?;
+        lisp::eval_string(&interpreter, "(let [y 10u32] (+ x y))")
+    }
+    fn is_macro(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+pub fn independent_interpreters() {
+    new_symbol("nested-eval", NestedEval).unwrap();
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    eval("(def x 1u32)");
+    assert_eq!(
+        eval("(let [y 2u32] (+ x y (nested-eval) x y))"),
+        SExpr::Value(Value::U32(116))
+    );
+    // interpreters keep their bindings when moved to other threads
+    let workers: Vec<_> = (0..4u32)
+        .map(|i| {
+            let interpreter = lisp::get_interpreter();
+            lisp::eval_string(&interpreter, &format!("(def x {}u32)", i)).unwrap();
+            thread::spawn(move || {
+                lisp::eval_string(&interpreter, "(defunc f [y] (+ x y))").unwrap();
+                lisp::eval_string(&interpreter, "(f (nested-eval))").unwrap()
+            })
+        })
+        .collect();
+    let results: Vec<SExpr> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+    assert_eq!(
+        results,
+        (110..114u32)
+            .map(|n| SExpr::Value(Value::U32(n)))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(eval("x"), SExpr::Value(Value::U32(1)));
+}
+
+// Native code failing with a panic instead of an error
+#[derive(Debug)]
+struct Panicking;
+
+impl Symbol for Panicking {
+    fn eval(&self, _: Vec<SExpr>, _: &Envorinment) -> Result<SExpr, DovahkiinError> {
+        panic!("native symbol panicked")
+    }
+    fn is_macro(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+pub fn failed_evaluations_keep_environment() {
+    new_symbol("panicking", Panicking).unwrap();
+    let failing = [
+        // in a binding, after other bindings
+        "(let [a 1u32 b (+ a \"x\")] a)",
+        "(let [a 1u32] (+ a \"x\"))",
+        "(let [a 1u32] (let [b 2u32] (do (bad a))))",
+        "(bad 1u32)",
+        "(map (lambda [q] (bad q)) [1u32 2u32])",
+        "(let [a 1u32] (forever a))",
+        "(let [a 1u32] (grow [a] 64u32))",
+        "(let [a 1u32] (undefined-function a))",
+    ];
+    for &scoping in &[Scoping::Lexical, Scoping::Dynamic] {
+        let interpreter = lisp::get_interpreter();
+        interpreter.set_scoping(scoping);
+        let eval = |code: &str| lisp::eval_string(&interpreter, code);
+        eval("(def a 0u32)").unwrap();
+        eval("(defunc bad [p] (let [b p] (+ b \"x\")))").unwrap();
+        eval("(defunc forever [p] (let [b p] (forever b)))").unwrap();
+        eval("(defunc grow [v n] (if (= n 0u32) v (grow (concat v v) (- n 1u32))))").unwrap();
+        interpreter.set_limits(Limits {
+            max_steps: Some(10000),
+            max_memory: Some(1 << 20),
+            ..Limits::default()
+        });
+        let bindings = interpreter.env().bindings.borrow().clone();
+        for code in failing.iter() {
+            assert!(eval(code).is_err(), "{}", code);
+            assert!(*interpreter.env().bindings.borrow() == bindings, "{}", code);
+            assert!(interpreter.env().frame.borrow().is_none(), "{}", code);
+            assert_eq!(eval("a").unwrap(), SExpr::Value(Value::U32(0)), "{}", code);
+        }
+        // panics from native code unwind the bindings too
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            lisp::eval_string(&interpreter, "(let [a 1u32] (bad (panicking a)))")
+        }));
+        assert!(result.is_err());
+        assert!(*interpreter.env().bindings.borrow() == bindings);
+        assert!(interpreter.env().frame.borrow().is_none());
+        assert_eq!(
+            eval("(let [b 2u32] (+ a b))").unwrap(),
+            SExpr::Value(Value::U32(2))
+        );
+    }
+}
+
+#[test]
+pub fn exceptions() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code);
+    let string = |str: &str| SExpr::Value(Value::String(str.to_string()));
+    // native errors are caught as maps of their message, kind and data
+    assert_eq!(
+        eval("(try (/ 1u32 0u32) (catch e (:message e)))").unwrap(),
+        string("Division by zero")
+    );
+    // integer overflow is an error too, in debug and release builds
+    for code in &[
+        "(- 0u32 1u32)",
+        "(+ 255u8 1u8)",
+        "(* 4294967296u64 4294967296u64)",
+        "(/ -128i8 -1i8)",
+        "(inc 127i8)",
+    ] {
+        assert_eq!(
+            eval(&format!("(try {} (catch e (:kind e)))", code)).unwrap(),
+            string("runtime"),
+            "{}",
+            code
+        );
+    }
+    assert_eq!(
+        eval("(- 1.5f32 2.5f32)").unwrap(),
+        SExpr::Value(Value::F32(-1.0))
+    );
+    assert_eq!(
+        eval("(try (+ 1u32 \"a\") 2u32 (catch e (:kind e)))").unwrap(),
+        string("type")
+    );
+    // ex-info data is kept, also when thrown inside a function
+    eval(
+        "(defunc age [row] \
+           (if (< (:age row) 0i32) \
+             (throw (ex-info \"bad row\" (hash-map :row row))) \
+             (:age row)))",
+    )
+    .unwrap();
+    assert_eq!(
+        eval("(try (age (hash-map :age -1i32)) (catch e (:age (:row (:data e)))))").unwrap(),
+        SExpr::Value(Value::I32(-1))
+    );
+    assert_eq!(
+        eval(
+            "(map (lambda [row] (try (age row) (catch e 0i32))) \
+                  [(hash-map :age 3i32) (hash-map :age -1i32) (hash-map :age 5i32)])"
+        )
+        .unwrap(),
+        SExpr::Vec(vec![
+            SExpr::Value(Value::I32(3)),
+            SExpr::Value(Value::I32(0)),
+            SExpr::Value(Value::I32(5)),
+        ])
+    );
+    // finally runs after the body and the handler, the result is theirs
+    assert_eq!(
+        eval("(try 1u32 (finally (def cleaned 1u32)))").unwrap(),
+        SExpr::Value(Value::U32(1))
+    );
+    assert_eq!(eval("cleaned").unwrap(), SExpr::Value(Value::U32(1)));
+    assert_eq!(
+        eval("(try (throw \"boom\") (catch e (:message e)) (finally (def cleaned 2u32)))").unwrap(),
+        string("boom")
+    );
+    assert_eq!(eval("cleaned").unwrap(), SExpr::Value(Value::U32(2)));
+    let error = eval("(try (throw \"boom\") (finally (def cleaned 3u32)))").unwrap_err();
+    assert_eq!(
+        (error.kind, error.message.as_str()),
+        (ErrorKind::User, "boom")
+    );
+    assert_eq!(eval("cleaned").unwrap(), SExpr::Value(Value::U32(3)));
+    // uncaught ex-info reaches the host with its data
+    let error = eval("(throw (ex-info \"bad\" (hash-map :a 1u32)))").unwrap_err();
+    assert_eq!(error.message, "bad");
+    match error.payload {
+        Some(Value::Map(ref data)) => assert_eq!(data.get("a"), &Value::U32(1)),
+        ref payload => panic!("{:?}", payload),
+    }
+    // limits cannot be caught
+    eval("(defunc forever [x] (forever x))").unwrap();
+    interpreter.set_limits(Limits {
+        max_steps: Some(1000),
+        ..Limits::default()
+    });
+    let error = eval("(try (forever 1u32) (catch e :caught))").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::LimitExceeded);
+    assert_eq!(
+        eval("(catch e 1u32)").unwrap_err().message,
+        "catch can only be used inside try"
+    );
+}
+
+#[test]
+pub fn variadic_lambdas() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code);
+    let u32s =
+        |nums: &[u32]| SExpr::Vec(nums.iter().map(|n| SExpr::Value(Value::U32(*n))).collect());
+    // wrong number of arguments is an arity error, not a panic or an outer binding
+    eval("(defunc add [a b] (+ a b))").unwrap();
+    eval("(def b 10u32)").unwrap();
+    for code in &["(add 1u32 2u32 3u32)", "(add 1u32)", "((lambda [x] x))"] {
+        let error = eval(code).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Arity, "{}", code);
+    }
+    assert_eq!(
+        eval("(add 1u32)").unwrap_err().root_cause().message,
+        "Function expect 2 parameters but found 1"
+    );
+    // the rest parameter collects the arguments left into a vector
+    eval("(defunc tail [x & rest] rest)").unwrap();
+    assert_eq!(eval("(tail 1u32 2u32 3u32)").unwrap(), u32s(&[2, 3]));
+    assert_eq!(eval("(tail 1u32)").unwrap(), u32s(&[]));
+    assert_eq!(eval("(tail)").unwrap_err().kind, ErrorKind::Arity);
+    assert_eq!(eval("((lambda [& all] all) 1u32)").unwrap(), u32s(&[1]));
+    for code in &[
+        "(lambda [x &] x)",
+        "(lambda [& a b] a)",
+        "(lambda [& & a] a)",
+    ] {
+        assert_eq!(eval(code).unwrap_err().kind, ErrorKind::Type, "{}", code);
+    }
+    // apply spreads the last argument into the call
+    assert_eq!(
+        eval("(apply add [1u32 2u32])").unwrap(),
+        SExpr::Value(Value::U32(3))
+    );
+    assert_eq!(
+        eval("(apply add 1u32 [b])").unwrap(),
+        SExpr::Value(Value::U32(11))
+    );
+    assert_eq!(
+        eval("(apply tail 1u32 [2u32 3u32])").unwrap(),
+        u32s(&[2, 3])
+    );
+    assert_eq!(
+        eval("(apply + 1u32 [2u32 3u32])").unwrap(),
+        SExpr::Value(Value::U32(6))
+    );
+    assert_eq!(
+        eval("(apply add [1u32])").unwrap_err().kind,
+        ErrorKind::Arity
+    );
+    assert_eq!(
+        eval("(apply + 1u32 [2 3])").unwrap(),
+        SExpr::Value(Value::U32(6))
+    );
+    // quoted items are passed as data, not evaluated again
+    let symbol = |name: &str| SExpr::ISymbol(hash_str(name), name.to_string());
+    assert_eq!(
+        eval("(apply (lambda [& forms] forms) ['(+ 1u32 2u32) 'b])")
+            .unwrap()
+            .strip_locations(),
+        SExpr::Vec(vec![
+            SExpr::List(vec![
+                symbol("+"),
+                SExpr::Value(Value::U32(1)),
+                SExpr::Value(Value::U32(2)),
+            ]),
+            symbol("b"),
+        ])
+    );
+}
+
+#[test]
+pub fn arguments_evaluated_once() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    let symbol = |name: &str| SExpr::ISymbol(hash_str(name), name.to_string());
+    // items are evaluated by map, the lambda gets them as data
+    assert_eq!(
+        eval("(let [x 5u32] (map (lambda [s] s) ['x]))"),
+        SExpr::Vec(vec![symbol("x")])
+    );
+    assert_eq!(
+        eval("(map (lambda [form] form) ['(+ 1u32 2u32)])").strip_locations(),
+        SExpr::Vec(vec![SExpr::List(vec![
+            symbol("+"),
+            SExpr::Value(Value::U32(1)),
+            SExpr::Value(Value::U32(2)),
+        ])])
+    );
+    assert_eq!(
+        eval("(filter (lambda [form] (= form 'y)) ['x 'y])"),
+        SExpr::Vec(vec![symbol("y")])
+    );
+    // and arguments of a call once by the caller
+    eval("(defunc id [x] x)");
+    assert_eq!(eval("(id 'x)"), symbol("x"));
+    assert_eq!(eval("(id (id 'x))"), symbol("x"));
+}
+
+// Number of dynamic bindings, counting the shadowed ones
+#[derive(Debug)]
+struct BindingCount;
+
+impl Symbol for BindingCount {
+    fn eval(&self, _: Vec<SExpr>, env: &Envorinment) -> Result<SExpr, DovahkiinError> {
+        let count = env
+            .bindings
+            .borrow()
+            .values()
+            .map(|stack| stack.len())
+            .sum::<usize>();
+        Ok(SExpr::Value(Value::U64(count as u64)))
+    }
+    fn is_macro(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+pub fn tail_calls_with_dynamic_scoping() {
+    new_symbol("binding-count", BindingCount).unwrap();
+    let interpreter = lisp::get_interpreter();
+    interpreter.set_scoping(Scoping::Dynamic);
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    eval(
+        "(defunc count [n acc] \
+           (if (= n 0u32) acc (let [m (- n 1u32)] (count m (+ acc 1u32)))))",
+    );
+    assert_eq!(
+        eval("(count 100000u32 0u32)"),
+        SExpr::Value(Value::U32(100000))
+    );
+    // each call in a tail loop unbinds its parameters and lets before the next one
+    let bindings = |interpreter: &Interpreter| {
+        let bindings = interpreter.env().bindings.borrow();
+        let mut lengths: Vec<(u64, usize)> = bindings
+            .iter()
+            .map(|(&id, stack)| (id, stack.len()))
+            .collect();
+        lengths.sort();
+        lengths
+    };
+    eval(
+        "(defunc bottom [n] \
+           (if (= n 0u32) (binding-count) (let [m (- n 1u32)] (bottom m))))",
+    );
+    let before = bindings(&interpreter);
+    assert_eq!(eval("(bottom 10000u32)"), eval("(bottom 1u32)"));
+    assert_eq!(bindings(&interpreter), before);
+    // bindings of the callers are seen by nested calls, and gone after the call
+    eval("(defunc inner [] m)");
+    eval("(defunc outer [] (let [m 1u32] (+ (inner) 0u32)))");
+    assert_eq!(eval("(outer)"), SExpr::Value(Value::U32(1)));
+    assert_eq!(eval("m"), SExpr::ISymbol(hash_str("m"), "m".to_string()));
+    // expressions in tail position take one step each, like anywhere else
+    let steps = |scoping: Scoping| {
+        let interpreter = lisp::get_interpreter();
+        interpreter.set_scoping(scoping);
+        lisp::eval_string(&interpreter, "(defunc f [x] (when true (do x)))").unwrap();
+        (1..100)
+            .find(|&n| {
+                interpreter.set_limits(Limits {
+                    max_steps: Some(n),
+                    ..Limits::default()
+                });
+                lisp::eval_string(&interpreter, "(f 1u32)").is_ok()
+            })
+            .unwrap()
+    };
+    assert_eq!(steps(Scoping::Lexical), steps(Scoping::Dynamic));
+}
+
+#[test]
+pub fn printed_values_round_trip() {
+    let interpreter = lisp::get_interpreter();
+    let read = |code: &str| {
+        lisp::parse_to_expr(code)
+            .unwrap()
+            .remove(0)
+            .strip_locations()
+    };
+    let scalars = vec![
+        Value::Null,
+        Value::Bool(false),
+        Value::Char('\n'),
+        Value::I8(-8),
+        Value::I64(i64::min_value()),
+        Value::U64(u64::max_value()),
+        Value::F32(0.1),
+        Value::F64(-2.5e-300),
+        Value::String("tab\t \"quoted\" \\".to_string()),
+        Value::Bytes(Bytes::from_vec(vec![0, 255])),
+        Value::Id(Id::new(1, 255)),
+        Value::Pos2d32(Pos2d32 { x: 1.5, y: -2.0 }),
+        Value::Pos3d64(Pos3d64 {
+            x: 1.0,
+            y: 2.0,
+            z: 1e10,
+        }),
+    ];
+    for value in scalars {
+        let printed = printer::print_value(&value);
+        assert_eq!(read(&printed), SExpr::Value(value), "{}", printed);
+    }
+    // collections are read back as the forms evaluating to them, arrays as vectors
+    let items = vec![Value::U8(1), Value::String("a".to_string())];
+    let array = Value::Array(items.clone());
+    let printed = printer::print_readable(&SExpr::Value(array.clone())).unwrap();
+    assert_eq!(
+        lisp::eval_string(&interpreter, &printed).unwrap(),
+        SExpr::Vec(items.into_iter().map(SExpr::Value).collect())
+    );
+    let mut map = types::OwnedMap::new();
+    map.insert("items", array);
+    map.insert(":not a keyword", Value::U32(2));
+    let printed = printer::print_readable(&SExpr::Value(Value::Map(map.clone()))).unwrap();
+    match lisp::eval_string(&interpreter, &printed).unwrap() {
+        SExpr::Value(Value::Map(reread)) => {
+            assert_eq!(reread.into_string_map(), map.into_string_map())
+        }
+        reread => panic!("{:?}", reread),
+    }
+    // values without a literal are rejected
+    let mut by_id = types::OwnedMap::new();
+    by_id.insert_key_id(key_hash("a"), Value::U8(1));
+    for value in vec![
+        Value::F64(::std::f64::NAN),
+        Value::Array(vec![Value::F32(::std::f32::INFINITY)]),
+        Value::NA,
+        Value::Map(by_id),
+    ] {
+        let error = printer::print_readable(&SExpr::Value(value)).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Type);
+    }
+}
+
+#[test]
+pub fn not_equals() {
+    let interpreter = lisp::get_interpreter();
+    let eval = |code: &str| lisp::eval_string(&interpreter, code).unwrap();
+    assert_eq!(eval("(!= 1u32 2u32)"), SExpr::Value(Value::Bool(true)));
+    assert_eq!(eval("(!= 1u32 1u32)"), SExpr::Value(Value::Bool(false)));
+    assert_eq!(eval("(!= \"a\" \"b\")"), SExpr::Value(Value::Bool(true)));
+    assert_eq!(eval("(!= 2 2u8)"), SExpr::Value(Value::Bool(false)));
+    assert_eq!(eval("(not (!= :a :a))"), SExpr::Value(Value::Bool(true)));
+}